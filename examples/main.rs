@@ -10,11 +10,11 @@ fn main() {
             .map(|aabb| aabb.fit_in(1, 21))
             .collect::<Vec<bool>>()
     );
-    tree.merge(AABB::new(0.0, 0.0, 0.0, 1.0, 0.5, 0.75), Rgb::new(1, 1, 1));
+    tree.merge(AABB::new(0.0, 0.0, 0.0, 1.0, 0.5, 0.75), Rgb::new(1, 1, 1)).unwrap();
     println!("{:#?}", tree);
-    tree.merge(AABB::new(0.0, 0.0, 0.75, 1.0, 0.5, 1.0), Rgb::new(1, 1, 1));
+    tree.merge(AABB::new(0.0, 0.0, 0.75, 1.0, 0.5, 1.0), Rgb::new(1, 1, 1)).unwrap();
     println!("{:#?}", tree);
-    tree.merge(AABB::new(0.0, 0.5, 0.0, 1.0, 1.0, 1.0), Rgb::new(1, 1, 1));
+    tree.merge(AABB::new(0.0, 0.5, 0.0, 1.0, 1.0, 1.0), Rgb::new(1, 1, 1)).unwrap();
     println!("{:#?}", tree);
     println!(
         "{:?}",
@@ -1,14 +1,31 @@
-use crate::Orientation;
+use crate::{LocCode, Orientation};
 use std::fmt::Debug;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlaneAxis {
     X,
     Y,
     Z,
 }
 
+/// Plane orthogonal to an axis, at the given position along it.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Plane(f64, PlaneAxis);
 
+impl Plane {
+    pub fn new(position: f64, axis: PlaneAxis) -> Self {
+        Self(position, axis)
+    }
+
+    pub fn position(&self) -> f64 {
+        self.0
+    }
+
+    pub fn axis(&self) -> PlaneAxis {
+        self.1
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AABB {
     x1: f64,
@@ -47,78 +64,198 @@ impl AABB {
             x2: max(x2, x1),
             y2: max(y1, y2),
             z2: max(z1, z2),
-            orientation: Orientation::N,
+            orientation: Orientation::default(),
         }
     }
 
+    /// Exact bounds of a node in the unit cube of the tree.
+    pub fn from_loc_code<L: LocCode>(loc_code: L) -> Self {
+        let size = 1_f64 / (1_u64 << loc_code.get_depth()) as f64;
+        let (x, y, z) = loc_code.to_coords();
+        let (x, y, z) = (x as f64 * size, y as f64 * size, z as f64 * size);
+        Self::new(x, y, z, x + size, y + size, z + size)
+    }
+
+    pub fn x1(&self) -> f64 {
+        self.x1
+    }
+
+    pub fn y1(&self) -> f64 {
+        self.y1
+    }
+
+    pub fn z1(&self) -> f64 {
+        self.z1
+    }
+
+    pub fn x2(&self) -> f64 {
+        self.x2
+    }
+
+    pub fn y2(&self) -> f64 {
+        self.y2
+    }
+
+    pub fn z2(&self) -> f64 {
+        self.z2
+    }
+
+    /// Lowest corner of the box.
+    pub fn min(&self) -> (f64, f64, f64) {
+        (self.x1, self.y1, self.z1)
+    }
+
+    /// Highest corner of the box.
+    pub fn max(&self) -> (f64, f64, f64) {
+        (self.x2, self.y2, self.z2)
+    }
+
+    pub fn center(&self) -> (f64, f64, f64) {
+        (
+            (self.x1 + self.x2) / 2.0,
+            (self.y1 + self.y2) / 2.0,
+            (self.z1 + self.z2) / 2.0,
+        )
+    }
+
+    /// Edge lengths of the box along each axis.
+    pub fn extents(&self) -> (f64, f64, f64) {
+        (self.x2 - self.x1, self.y2 - self.y1, self.z2 - self.z1)
+    }
+
+    pub fn volume(&self) -> f64 {
+        let (x, y, z) = self.extents();
+        x * y * z
+    }
+
+    /// Whether the point is inside the box or on its boundary.
+    pub fn contains_point(&self, point: (f64, f64, f64)) -> bool {
+        self.x1 <= point.0
+            && point.0 <= self.x2
+            && self.y1 <= point.1
+            && point.1 <= self.y2
+            && self.z1 <= point.2
+            && point.2 <= self.z2
+    }
+
+    /// Whether `other` is entirely inside the box.
+    pub fn contains(&self, other: &Self) -> bool {
+        self.contains_point(other.min()) && self.contains_point(other.max())
+    }
+
+    /// Whether the boxes share some volume, touching boxes do not intersect.
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.x1 < other.x2
+            && other.x1 < self.x2
+            && self.y1 < other.y2
+            && other.y1 < self.y2
+            && self.z1 < other.z2
+            && other.z1 < self.z2
+    }
+
+    /// Volume shared by both boxes, `None` if they do not intersect.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        if self.intersects(other) {
+            Some(Self::new(
+                max(self.x1, other.x1),
+                max(self.y1, other.y1),
+                max(self.z1, other.z1),
+                min(self.x2, other.x2),
+                min(self.y2, other.y2),
+                min(self.z2, other.z2),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(
+            min(self.x1, other.x1),
+            min(self.y1, other.y1),
+            min(self.z1, other.z1),
+            max(self.x2, other.x2),
+            max(self.y2, other.y2),
+            max(self.z2, other.z2),
+        )
+    }
+
+    /// Grow the box by `margin` on every side, shrinking it if negative.
+    pub fn expand(self, margin: f64) -> Self {
+        let center = self.center();
+        let (x, y, z) = self.extents();
+        let half = (
+            max(x / 2.0 + margin, 0.0),
+            max(y / 2.0 + margin, 0.0),
+            max(z / 2.0 + margin, 0.0),
+        );
+        Self::new(
+            center.0 - half.0,
+            center.1 - half.1,
+            center.2 - half.2,
+            center.0 + half.0,
+            center.1 + half.1,
+            center.2 + half.2,
+        )
+        .with_orientation(self.orientation)
+    }
+
     pub fn with_orientation(mut self, orientation: Orientation) -> Self {
         self.orientation = orientation;
         self
     }
 
     pub fn slice(self, plane: Plane) -> Vec<Self> {
-        let orientation = self.orientation;
-        match plane.1 {
-            PlaneAxis::X => {
-                if self.x1 < plane.0 && self.x2 > plane.0 {
-                    vec![
-                        Self::new(self.x1, self.y1, self.z1, plane.0, self.y2, self.z2)
-                            .with_orientation(orientation | Orientation::L),
-                        Self::new(plane.0, self.y1, self.z1, self.x2, self.y2, self.z2)
-                            .with_orientation(orientation | Orientation::R),
-                    ]
-                } else if self.x2 <= plane.0 {
-                    vec![self.with_orientation(orientation | Orientation::L)]
-                } else {
-                    vec![self.with_orientation(orientation | Orientation::R)]
+        let Plane(position, axis) = plane;
+        let below = self
+            .clone()
+            .with_orientation(self.orientation.with_axis(axis, false));
+        let above = self
+            .clone()
+            .with_orientation(self.orientation.with_axis(axis, true));
+        let (min, max) = match axis {
+            PlaneAxis::X => (self.x1, self.x2),
+            PlaneAxis::Y => (self.y1, self.y2),
+            PlaneAxis::Z => (self.z1, self.z2),
+        };
+        if min < position && max > position {
+            let (mut below, mut above) = (below, above);
+            match axis {
+                PlaneAxis::X => {
+                    below.x2 = position;
+                    above.x1 = position;
                 }
-            }
-            PlaneAxis::Y => {
-                if self.y1 < plane.0 && self.y2 > plane.0 {
-                    vec![
-                        Self::new(self.x1, self.y1, self.z1, self.x2, plane.0, self.z2)
-                            .with_orientation(orientation | Orientation::D),
-                        Self::new(self.x1, plane.0, self.z1, self.x2, self.y2, self.z2)
-                            .with_orientation(orientation | Orientation::U),
-                    ]
-                } else if self.y2 <= plane.0 {
-                    vec![self.with_orientation(orientation | Orientation::D)]
-                } else {
-                    vec![self.with_orientation(orientation | Orientation::U)]
+                PlaneAxis::Y => {
+                    below.y2 = position;
+                    above.y1 = position;
                 }
-            }
-            PlaneAxis::Z => {
-                if self.z1 < plane.0 && self.z2 > plane.0 {
-                    vec![
-                        Self::new(self.x1, self.y1, self.z1, self.x2, self.y2, plane.0)
-                            .with_orientation(orientation | Orientation::B),
-                        Self::new(self.x1, self.y1, plane.0, self.x2, self.y2, self.z2)
-                            .with_orientation(orientation | Orientation::F),
-                    ]
-                } else if self.z2 <= plane.0 {
-                    vec![self.with_orientation(orientation | Orientation::B)]
-                } else {
-                    vec![self.with_orientation(orientation | Orientation::F)]
+                PlaneAxis::Z => {
+                    below.z2 = position;
+                    above.z1 = position;
                 }
             }
+            vec![below, above]
+        } else if max <= position {
+            vec![below]
+        } else {
+            vec![above]
         }
     }
 
     pub fn explode(self, center: (f64, f64, f64)) -> Vec<Self> {
         self.slice(Plane(center.0, PlaneAxis::X))
             .into_iter()
-            .map(|aabb| aabb.slice(Plane(center.1, PlaneAxis::Y)))
-            .flatten()
-            .map(|aabb| aabb.slice(Plane(center.2, PlaneAxis::Z)))
-            .flatten()
+            .flat_map(|aabb| aabb.slice(Plane(center.1, PlaneAxis::Y)))
+            .flat_map(|aabb| aabb.slice(Plane(center.2, PlaneAxis::Z)))
             .collect()
     }
 
     pub fn fit_in(&self, depth: u32, max_depth: u32) -> bool {
         let edge_size = 1_f64 / (2_usize.pow(depth) as f64);
-        (((self.x1 - self.x2).abs() - edge_size).abs() < std::f64::EPSILON
-            && ((self.y1 - self.y2).abs() - edge_size).abs() < std::f64::EPSILON
-            && ((self.z1 - self.z2).abs() - edge_size).abs() < std::f64::EPSILON)
+        (((self.x1 - self.x2).abs() - edge_size).abs() < f64::EPSILON
+            && ((self.y1 - self.y2).abs() - edge_size).abs() < f64::EPSILON
+            && ((self.z1 - self.z2).abs() - edge_size).abs() < f64::EPSILON)
             || depth == max_depth
     }
 
@@ -157,3 +294,33 @@ impl AABB {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_loc_code() {
+        let aabb = AABB::from_loc_code(u32::from_coords(1, 2, 3, 2));
+        assert_eq!(aabb.min(), (0.25, 0.5, 0.75));
+        assert_eq!(aabb.max(), (0.5, 0.75, 1.0));
+        assert_eq!(AABB::from_loc_code(u8::root()).volume(), 1.0);
+    }
+
+    #[test]
+    fn geometry() {
+        let a = AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5);
+        let b = AABB::new(0.25, 0.25, 0.25, 1.0, 1.0, 1.0);
+        assert_eq!(a.center(), (0.25, 0.25, 0.25));
+        assert_eq!(b.extents(), (0.75, 0.75, 0.75));
+        assert!(a.intersects(&b));
+        assert!(!a.intersects(&AABB::new(0.5, 0.0, 0.0, 1.0, 0.5, 0.5)));
+        assert_eq!(a.intersection(&b).unwrap().volume(), 0.25 * 0.25 * 0.25);
+        let union = a.union(&b);
+        assert!(union.contains(&a) && union.contains(&b));
+        assert!(!a.contains(&b));
+        assert!(a.contains_point((0.5, 0.0, 0.25)));
+        assert_eq!(a.clone().expand(0.25).min(), (-0.25, -0.25, -0.25));
+        assert_eq!(a.expand(-1.0).volume(), 0.0);
+    }
+}
@@ -0,0 +1,161 @@
+//
+// bulk.rs in generic_octree
+// Description:
+// Bottom-up construction of an Octree from voxels.
+//
+
+use rayon::prelude::*;
+
+use crate::linear_storage::sort_keep_last;
+use crate::{LocCode, Octree, OctreeError, OctreeNode, OctreeStorage};
+
+/// Depth of the subtrees built in parallel.
+const SPLIT_DEPTH: u32 = 2;
+
+/// Collapse the sorted nodes of `depth` up to `until`, moving every node that
+/// cannot be collapsed into `leaves` and returning the ones left at `until`.
+fn collapse_sorted<L, D>(
+    mut level: Vec<(L, D)>,
+    depth: u32,
+    until: u32,
+    leaves: &mut Vec<(L, D)>,
+) -> Vec<(L, D)>
+where
+    L: LocCode,
+    D: Copy + PartialEq,
+{
+    for _ in until..depth {
+        let mut parents = vec![];
+        for siblings in level.chunk_by(|a, b| a.0.parent() == b.0.parent()) {
+            let (first, data) = siblings[0];
+            if siblings.len() == 8 && siblings.iter().all(|(_, other)| *other == data) {
+                let parent = first.parent().expect("nodes below `until` have a parent");
+                parents.push((parent, data));
+            } else {
+                leaves.extend_from_slice(siblings);
+            }
+        }
+        level = parents;
+    }
+    level
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Build a tree from `(x, y, z, data)` voxels of the `2^max_depth` grid,
+    /// collapsing them bottom-up in parallel.
+    ///
+    /// Voxels outside of the grid are ignored, and the last one wins when a
+    /// voxel is given more than once.
+    pub fn from_voxels<I>(max_depth: u32, voxels: I) -> Result<Self, OctreeError>
+    where
+        I: IntoIterator<Item = (u64, u64, u64, D)>,
+    {
+        Self::from_voxels_at(max_depth, max_depth, voxels)
+    }
+
+    /// Build a tree from voxels of the coarser `2^voxel_depth` grid, each
+    /// one filling a whole node at `voxel_depth`.
+    pub(crate) fn from_voxels_at<I>(
+        max_depth: u32,
+        voxel_depth: u32,
+        voxels: I,
+    ) -> Result<Self, OctreeError>
+    where
+        I: IntoIterator<Item = (u64, u64, u64, D)>,
+    {
+        let mut tree = Self::try_new(max_depth)?;
+        let voxel_depth = voxel_depth.min(max_depth);
+        let side = 1_u64 << voxel_depth;
+        let voxels: Vec<(u64, u64, u64, D)> = voxels
+            .into_iter()
+            .filter(|(x, y, z, _)| *x < side && *y < side && *z < side)
+            .collect();
+        let mut codes: Vec<(T, D)> = voxels
+            .into_par_iter()
+            .map(|(x, y, z, data)| (T::from_coords(x, y, z, voxel_depth), data))
+            .collect();
+        sort_keep_last(&mut codes, |codes| codes.par_sort_by_key(|(code, _)| *code));
+
+        let split = voxel_depth.min(SPLIT_DEPTH);
+        let subtrees: Vec<_> = codes
+            .chunk_by(|a, b| a.0.ancestor_at(split) == b.0.ancestor_at(split))
+            .collect::<Vec<&[(T, D)]>>()
+            .into_par_iter()
+            .map(|subtree| {
+                let mut leaves = vec![];
+                let top = collapse_sorted(subtree.to_vec(), voxel_depth, split, &mut leaves);
+                (leaves, top)
+            })
+            .collect();
+
+        let mut leaves = vec![];
+        let mut tops = vec![];
+        for (subtree, top) in subtrees {
+            leaves.extend(subtree);
+            tops.extend(top);
+        }
+        let root = collapse_sorted(tops, split, 0, &mut leaves);
+        leaves.extend(root);
+
+        tree.content = S::with_capacity(leaves.len());
+        for (code, data) in leaves {
+            tree.content.insert(code, OctreeNode::new(data));
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Morton;
+
+    #[test]
+    fn collapse_and_duplicates() {
+        let mut voxels = vec![];
+        for index in 0..8 {
+            let (x, y, z) = (index >> 2 & 1, index >> 1 & 1, index & 1);
+            voxels.push((x, y, z, 1));
+            voxels.push((x + 2, y, z, 3));
+            voxels.push((x, y, z + 2, 4));
+        }
+        // Overwritten, so its octant stays uniform.
+        voxels.insert(0, (2, 1, 0, 9));
+        // Overwriting, so its octant is no longer uniform.
+        voxels.push((0, 1, 3, 5));
+        // Outside of the grid.
+        voxels.push((99, 0, 0, 6));
+        let tree: Octree<u32, u8> = Octree::from_voxels(2, voxels).unwrap();
+        assert_eq!(tree.size(), 10);
+        let octant = |x, y, z| {
+            tree.lookup(Morton::new(u32::from_coords(x, y, z, 1)).unwrap())
+                .map(|node| node.data)
+        };
+        assert_eq!(octant(0, 0, 0), Some(1));
+        assert_eq!(octant(1, 0, 0), Some(3));
+        assert_eq!(octant(0, 0, 1), None);
+        let leaf = |voxel| {
+            tree.leaf_at(voxel)
+                .map(|(code, node)| (code.get().get_depth(), node.data))
+        };
+        assert_eq!(leaf((0, 1, 3)), Some((2, 5)));
+        assert_eq!(leaf((1, 1, 3)), Some((2, 4)));
+    }
+
+    #[test]
+    fn full_and_duplicates() {
+        let voxels = (0..8).map(|index| (index & 1, index >> 1 & 1, index >> 2, 5));
+        let tree: Octree<u32, u8> = Octree::from_voxels(1, voxels).unwrap();
+        assert_eq!(tree.size(), 1);
+        assert_eq!(tree.lookup(Morton::root()).map(|node| node.data), Some(5));
+        let tree: Octree<u32, u8> =
+            Octree::from_voxels(2, vec![(1, 2, 3, 1), (1, 2, 3, 2)]).unwrap();
+        assert_eq!(tree.leaf_at((1, 2, 3)).map(|(_, node)| node.data), Some(2));
+        assert!(Octree::<u8, u8>::from_voxels(3, vec![]).is_err());
+    }
+}
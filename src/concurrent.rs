@@ -0,0 +1,312 @@
+//
+// concurrent.rs in generic_octree
+// Description:
+// Octree sharded by top-level octant for edits from many threads.
+//
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::octree::{FITS_MAX_DEPTH, TOO_DEEP};
+use crate::{LocCode, Morton, Octree, OctreeError, OctreeNode, WorldTransform, AABB};
+
+/// Octree split into 8 shards, one per top-level octant, each behind its
+/// own lock, so `merge`, `insert` and `remove` take `&self` and edits of
+/// different octants run in parallel, from a `par_iter` for instance.
+///
+/// A root leaf is kept as 8 identical top-level leaves, siblings being
+/// collapsed into the root by `into_octree` once every shard is settled.
+#[derive(Debug)]
+pub struct ConcurrentOctree<L: LocCode, D: Send + Sync> {
+    shards: Vec<RwLock<Octree<L, D>>>,
+    max_depth: u32,
+    world_transform: Option<WorldTransform>,
+}
+
+impl<L, D> ConcurrentOctree<L, D>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    /// Panics if `max_depth` is deeper than `L::MAX_LEVEL`, see `try_new`.
+    pub fn new(max_depth: u32) -> Self {
+        Self::try_new(max_depth).expect(TOO_DEEP)
+    }
+
+    /// Create an empty tree, failing if `max_depth` is deeper than
+    /// `L::MAX_LEVEL`.
+    pub fn try_new(max_depth: u32) -> Result<Self, OctreeError> {
+        // Shards always hold the top-level octants, even for a single voxel.
+        let shard_depth = max_depth.max(1);
+        Octree::<L, D>::try_new(max_depth)?;
+        let shards = (0..8)
+            .map(|_| Octree::try_new(shard_depth).map(RwLock::new))
+            .collect::<Result<Vec<_>, OctreeError>>()?;
+        Ok(Self {
+            shards,
+            max_depth,
+            world_transform: None,
+        })
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Transform placing the tree in world space, the unit cube when unset.
+    pub fn world_transform(&self) -> WorldTransform {
+        self.world_transform.unwrap_or_default()
+    }
+
+    pub fn set_world_transform(&mut self, world_transform: Option<WorldTransform>) {
+        self.world_transform = world_transform;
+    }
+
+    /// Shard holding a code, `None` for the root.
+    fn route(loc_code: L) -> Option<usize> {
+        let raw: u128 = loc_code.ancestor_at(1)?.into();
+        Some((raw & 7) as usize)
+    }
+
+    fn read(&self, shard: usize) -> RwLockReadGuard<'_, Octree<L, D>> {
+        self.shards[shard]
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn write(&self, shard: usize) -> RwLockWriteGuard<'_, Octree<L, D>> {
+        self.shards[shard]
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Data of the root if every top-level octant is the same leaf.
+    fn root_data(&self) -> Option<D> {
+        let mut datas = L::root().children().enumerate().map(|(shard, top)| {
+            self.read(shard)
+                .lookup(Morton::new_unchecked(top))
+                .map(|node| node.data)
+        });
+        let first = datas.next()??;
+        if datas.all(|data| data == Some(first)) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    /// Data of the node at exactly this code, as `Octree::lookup`.
+    pub fn lookup(&self, loc_code: Morton<L>) -> Option<D> {
+        match Self::route(loc_code.get()) {
+            Some(shard) => self.read(shard).lookup(loc_code).map(|node| node.data),
+            None => self.root_data(),
+        }
+    }
+
+    /// Leaf containing the voxel at `(x, y, z)` of the `2^max_depth` grid,
+    /// as `Octree::leaf_at`.
+    pub fn leaf_at(&self, voxel: (u64, u64, u64)) -> Option<(Morton<L>, D)> {
+        let side = 1_u64 << self.max_depth;
+        if voxel.0 >= side || voxel.1 >= side || voxel.2 >= side {
+            return None;
+        }
+        if let Some(data) = self.root_data() {
+            return Some((Morton::root(), data));
+        }
+        let code = L::from_coords(voxel.0, voxel.1, voxel.2, self.max_depth);
+        let shard = Self::route(code)?;
+        let shard = self.read(shard);
+        shard
+            .leaf_at(voxel)
+            .map(|(loc_code, node)| (loc_code, node.data))
+    }
+
+    /// Insert a node, failing if it is deeper than `max_depth`.
+    pub fn insert(
+        &self,
+        location: Morton<L>,
+        node: OctreeNode<D>,
+    ) -> Result<Morton<L>, OctreeError> {
+        let depth = location.get().get_depth();
+        if depth > self.max_depth {
+            return Err(OctreeError::DepthOverflow {
+                depth,
+                max_depth: self.max_depth,
+            });
+        }
+        match Self::route(location.get()) {
+            Some(shard) => {
+                self.write(shard).insert(location, node)?;
+            }
+            None => {
+                for (shard, top) in L::root().children().enumerate() {
+                    self.write(shard)
+                        .insert(Morton::new_unchecked(top), OctreeNode::new(node.data))?;
+                }
+            }
+        }
+        Ok(location)
+    }
+
+    /// Remove the node at exactly this code, as `Octree::remove_node`.
+    pub fn remove(&self, loc_code: Morton<L>) {
+        match Self::route(loc_code.get()) {
+            Some(shard) => self.write(shard).remove_node(loc_code),
+            None => {
+                if self.root_data().is_some() {
+                    for (shard, top) in L::root().children().enumerate() {
+                        self.write(shard).remove_node(Morton::new_unchecked(top));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Merge an AABB, each top-level octant it overlaps being edited under
+    /// its own lock.
+    pub fn merge(&self, aabb: AABB, data: D) -> Result<(), OctreeError> {
+        if self.max_depth == 0 {
+            // A single voxel, any overlap fills it.
+            if aabb.intersects(&AABB::from_loc_code(L::root())) {
+                self.insert(Morton::root(), OctreeNode::new(data))?;
+            }
+            return Ok(());
+        }
+        for (shard, top) in L::root().children().enumerate() {
+            if let Some(part) = aabb.intersection(&AABB::from_loc_code(top)) {
+                self.write(shard).merge(part, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of nodes, a root leaf counting as its 8 top-level octants.
+    pub fn size(&self) -> usize {
+        (0..8).map(|shard| self.read(shard).size()).sum()
+    }
+
+    /// Gather the shards back into a single tree, collapsing the top-level
+    /// octants into the root when they all hold the same data.
+    pub fn into_octree(self) -> Octree<L, D> {
+        let mut tree: Octree<L, D> = Octree::new(self.max_depth);
+        tree.set_world_transform(self.world_transform);
+        for shard in self.shards {
+            let shard = shard
+                .into_inner()
+                .unwrap_or_else(|error| error.into_inner());
+            for (loc_code, node) in shard.iter() {
+                tree.content.insert(loc_code, OctreeNode::new(node.data));
+            }
+        }
+        tree.collapse(vec![L::root()]);
+        tree
+    }
+}
+
+impl<L, D> From<Octree<L, D>> for ConcurrentOctree<L, D>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    fn from(tree: Octree<L, D>) -> Self {
+        let mut concurrent = Self::new(tree.max_depth());
+        concurrent.world_transform = tree.world_transform;
+        for (loc_code, node) in tree.iter() {
+            concurrent
+                .insert(Morton::new_unchecked(loc_code), OctreeNode::new(node.data))
+                .expect(FITS_MAX_DEPTH);
+        }
+        concurrent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::prelude::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn boxes() -> Vec<(AABB, u8)> {
+        (0..8)
+            .flat_map(|x| (0..8).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = (x as f64 / 8.0, y as f64 / 8.0);
+                let data = if y < 0.5 { 1 } else { 2 };
+                (AABB::new(x, y, 0.0, x + 0.125, y + 0.125, 1.0), data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_merges() {
+        let concurrent: ConcurrentOctree<u32, u8> = ConcurrentOctree::new(3);
+        boxes()
+            .into_par_iter()
+            .try_for_each(|(aabb, data)| concurrent.merge(aabb, data))
+            .unwrap();
+        let mut sequential: Octree<u32, u8> = Octree::new(3);
+        for (aabb, data) in boxes() {
+            sequential.merge(aabb, data).unwrap();
+        }
+        assert_eq!(concurrent.lookup(Morton::new(0b1_000).unwrap()), Some(1));
+        assert_eq!(concurrent.leaf_at((7, 7, 7)).map(|(_, data)| data), Some(2));
+
+        let tree = concurrent.into_octree();
+        assert_eq!(tree.size(), sequential.size());
+        for (code, node) in sequential.iter() {
+            let code = Morton::new(code).unwrap();
+            assert_eq!(tree.lookup(code).map(|node| node.data), Some(node.data));
+        }
+    }
+
+    #[test]
+    fn shards_are_independent() {
+        let concurrent: ConcurrentOctree<u32, u8> = ConcurrentOctree::new(2);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            let held = concurrent.write(0);
+            for (shard, top) in u32::root().children().enumerate().skip(1) {
+                let (concurrent, sender) = (&concurrent, sender.clone());
+                scope.spawn(move || {
+                    concurrent
+                        .insert(Morton::new(top).unwrap(), OctreeNode::new(shard as u8))
+                        .unwrap();
+                    sender.send(shard).unwrap();
+                });
+            }
+            // Inserts into the other shards go through while shard 0 is
+            // locked.
+            for _ in 1..8 {
+                receiver
+                    .recv_timeout(Duration::from_secs(10))
+                    .expect("an insert waited for another shard");
+            }
+            drop(held);
+        });
+        assert_eq!(concurrent.size(), 7);
+        assert_eq!(concurrent.leaf_at((3, 3, 3)).map(|(_, data)| data), Some(7));
+        assert_eq!(concurrent.leaf_at((0, 0, 0)), None);
+    }
+
+    #[test]
+    fn root_collapse() {
+        let mut concurrent: ConcurrentOctree<u32, u8> = ConcurrentOctree::new(2);
+        (0..8_u32).into_par_iter().for_each(|octant| {
+            concurrent
+                .insert(Morton::new(0b1_000 | octant).unwrap(), OctreeNode::new(4))
+                .unwrap();
+        });
+        assert_eq!(concurrent.lookup(Morton::root()), Some(4));
+        assert_eq!(concurrent.leaf_at((0, 3, 1)), Some((Morton::root(), 4)));
+        let transform = WorldTransform::new((1.0, 2.0, 3.0), 4.0);
+        concurrent.set_world_transform(Some(transform));
+        assert_eq!(concurrent.world_transform(), transform);
+        let tree = concurrent.into_octree();
+        assert_eq!(tree.size(), 1);
+        assert_eq!(tree.world_transform(), transform);
+        let concurrent = ConcurrentOctree::from(tree);
+        concurrent.remove(Morton::root());
+        assert_eq!(concurrent.size(), 0);
+    }
+}
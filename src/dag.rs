@@ -0,0 +1,363 @@
+//
+// dag.rs in generic_octree
+// Description:
+// Read-only sparse voxel DAG sharing identical subtrees of an Octree.
+//
+
+use hashbrown::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::{LocCode, Morton, Octree, OctreeNode, OctreeStorage, Orientation, WorldTransform};
+
+/// Node of a DAG, children being indices into the node list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+enum DagNode<D> {
+    Leaf(D),
+    Branch([Option<u32>; 8]),
+}
+
+/// Compressed, read-only copy of an Octree where identical subtrees are
+/// stored once and shared.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct OctreeDag<L, D> {
+    nodes: Vec<DagNode<D>>,
+    root: Option<u32>,
+    max_depth: u32,
+    tree_nodes: usize,
+    world_transform: Option<WorldTransform>,
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    marker: PhantomData<L>,
+}
+
+/// First leaf hit by a ray, see `OctreeDag::raycast`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit<'a, L: LocCode, D> {
+    pub loc_code: Morton<L>,
+    pub data: &'a D,
+    /// Ray parameter of the entry point, `origin + distance * direction`.
+    pub distance: f64,
+}
+
+/// Hash-conses the nodes of a tree while walking it.
+struct Builder<'a, T: LocCode, D: Send + Sync, S> {
+    tree: &'a Octree<T, D, S>,
+    internal: HashSet<T>,
+    nodes: Vec<DagNode<D>>,
+    shared: HashMap<DagNode<D>, u32>,
+    tree_nodes: usize,
+}
+
+impl<'a, T, D, S> Builder<'a, T, D, S>
+where
+    T: LocCode,
+    D: Copy + Eq + Hash + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    fn build(&mut self, loc_code: T) -> Option<u32> {
+        let node = if let Some(node) = self.tree.content.get(&loc_code) {
+            DagNode::Leaf(node.data)
+        } else if self.internal.contains(&loc_code) {
+            let mut children = [None; 8];
+            for (child, slot) in loc_code.children().zip(children.iter_mut()) {
+                *slot = self.build(child);
+            }
+            DagNode::Branch(children)
+        } else {
+            return None;
+        };
+        self.tree_nodes += 1;
+        let nodes = &mut self.nodes;
+        let index = *self.shared.entry(node).or_insert_with_key(|node| {
+            nodes.push(node.clone());
+            (nodes.len() - 1) as u32
+        });
+        Some(index)
+    }
+}
+
+impl<L, D> OctreeDag<L, D>
+where
+    L: LocCode,
+    D: Copy + Eq + Hash + Send + Sync,
+{
+    /// Compress a tree, sharing every repeated subtree.
+    pub fn new<S: OctreeStorage<L, D>>(tree: &Octree<L, D, S>) -> Self {
+        let mut builder = Builder {
+            tree,
+            internal: tree.ancestors(),
+            nodes: vec![],
+            shared: HashMap::new(),
+            tree_nodes: 0,
+        };
+        let root = builder.build(L::root());
+        Self {
+            nodes: builder.nodes,
+            root,
+            max_depth: tree.max_depth(),
+            tree_nodes: builder.tree_nodes,
+            world_transform: tree.world_transform,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Number of distinct nodes stored.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Nodes of the original tree, leaves and internal nodes, over the
+    /// nodes actually stored.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.nodes.is_empty() {
+            1.0
+        } else {
+            self.tree_nodes as f64 / self.nodes.len() as f64
+        }
+    }
+
+    fn child(&self, index: u32, octant: u8) -> Option<u32> {
+        match &self.nodes[index as usize] {
+            DagNode::Branch(children) => children[octant as usize],
+            DagNode::Leaf(_) => None,
+        }
+    }
+
+    /// Data of the leaf at exactly this code, as `Octree::lookup`.
+    pub fn lookup(&self, loc_code: Morton<L>) -> Option<&D> {
+        let index = loc_code
+            .path()
+            .try_fold(self.root?, |index, octant| self.child(index, octant))?;
+        match &self.nodes[index as usize] {
+            DagNode::Leaf(data) => Some(data),
+            DagNode::Branch(_) => None,
+        }
+    }
+
+    /// Leaf containing the voxel at `(x, y, z)` of the `2^max_depth` grid,
+    /// as `Octree::leaf_at`.
+    pub fn leaf_at(&self, voxel: (u64, u64, u64)) -> Option<(Morton<L>, &D)> {
+        let side = 1_u64 << self.max_depth;
+        if voxel.0 >= side || voxel.1 >= side || voxel.2 >= side {
+            return None;
+        }
+        let mut index = self.root?;
+        let mut loc_code = L::root();
+        for level in (0..self.max_depth).rev() {
+            if let DagNode::Leaf(data) = &self.nodes[index as usize] {
+                return Some((Morton::new_unchecked(loc_code), data));
+            }
+            let bit = |axis: u64| ((axis >> level) & 1) as u8;
+            let octant = bit(voxel.0) << 2 | bit(voxel.1) << 1 | bit(voxel.2);
+            index = self.child(index, octant)?;
+            loc_code = loc_code.checked_child(Orientation::try_from(octant).ok()?)?;
+        }
+        match &self.nodes[index as usize] {
+            DagNode::Leaf(data) => Some((Morton::new_unchecked(loc_code), data)),
+            DagNode::Branch(_) => None,
+        }
+    }
+
+    /// First leaf hit by the ray `origin + t * direction` for `t >= 0`, in
+    /// the unit cube of the tree.
+    pub fn raycast(
+        &self,
+        origin: (f64, f64, f64),
+        direction: (f64, f64, f64),
+    ) -> Option<RayHit<'_, L, D>> {
+        let ray = Ray {
+            origin: [origin.0, origin.1, origin.2],
+            direction: [direction.0, direction.1, direction.2],
+        };
+        let distance = ray.enter([0.0; 3], 1.0)?;
+        self.cast(&ray, self.root?, L::root(), [0.0; 3], 1.0, distance)
+    }
+
+    fn cast(
+        &self,
+        ray: &Ray,
+        index: u32,
+        loc_code: L,
+        min: [f64; 3],
+        size: f64,
+        distance: f64,
+    ) -> Option<RayHit<'_, L, D>> {
+        match &self.nodes[index as usize] {
+            DagNode::Leaf(data) => Some(RayHit {
+                loc_code: Morton::new_unchecked(loc_code),
+                data,
+                distance,
+            }),
+            DagNode::Branch(children) => {
+                let half = size / 2.0;
+                let mut hits = vec![];
+                let codes = loc_code.children();
+                for ((octant, child), code) in children.iter().enumerate().zip(codes) {
+                    if let Some(child) = child {
+                        let corner = |bit: usize, axis: usize| {
+                            min[axis] + if octant & bit != 0 { half } else { 0.0 }
+                        };
+                        let min = [corner(4, 0), corner(2, 1), corner(1, 2)];
+                        if let Some(distance) = ray.enter(min, half) {
+                            hits.push((distance, code, *child, min));
+                        }
+                    }
+                }
+                // Children are disjoint, the first one entered holds the hit.
+                hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                hits.into_iter().find_map(|(distance, code, child, min)| {
+                    self.cast(ray, child, code, min, half, distance)
+                })
+            }
+        }
+    }
+
+    /// Expand the DAG back into a tree.
+    pub fn to_octree(&self) -> Octree<L, D> {
+        let mut tree: Octree<L, D> = Octree::new(self.max_depth);
+        tree.set_world_transform(self.world_transform);
+        let mut stack: Vec<(u32, L)> = self
+            .root
+            .map(|root| (root, L::root()))
+            .into_iter()
+            .collect();
+        while let Some((index, loc_code)) = stack.pop() {
+            match &self.nodes[index as usize] {
+                DagNode::Leaf(data) => {
+                    tree.content.insert(loc_code, OctreeNode::new(*data));
+                }
+                DagNode::Branch(children) => {
+                    stack.extend(
+                        children
+                            .iter()
+                            .zip(loc_code.children())
+                            .filter_map(|(child, code)| child.map(|child| (child, code))),
+                    );
+                }
+            }
+        }
+        tree
+    }
+}
+
+impl<L, D, S> From<&Octree<L, D, S>> for OctreeDag<L, D>
+where
+    L: LocCode,
+    D: Copy + Eq + Hash + Send + Sync,
+    S: OctreeStorage<L, D>,
+{
+    fn from(tree: &Octree<L, D, S>) -> Self {
+        Self::new(tree)
+    }
+}
+
+/// Ray `origin + t * direction` for `t >= 0`.
+pub(crate) struct Ray {
+    pub(crate) origin: [f64; 3],
+    pub(crate) direction: [f64; 3],
+}
+
+impl Ray {
+    /// Parameter at which the ray enters the cube, `None` if it misses it.
+    pub(crate) fn enter(&self, min: [f64; 3], size: f64) -> Option<f64> {
+        let (mut near, mut far) = (0.0_f64, f64::INFINITY);
+        let axes = self
+            .origin
+            .iter()
+            .zip(self.direction.iter())
+            .zip(min.iter());
+        for ((&origin, &direction), &low) in axes {
+            let high = low + size;
+            if direction == 0.0 {
+                if origin < low || origin > high {
+                    return None;
+                }
+            } else {
+                let (a, b) = ((low - origin) / direction, (high - origin) / direction);
+                near = near.max(a.min(b));
+                far = far.min(a.max(b));
+            }
+        }
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    fn pillars() -> Octree<u32, u8> {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        for x in 0..4 {
+            let x = x as f64 / 4.0;
+            tree.merge(AABB::new(x, 0.0, 0.0, x + 0.125, 1.0, 0.125), 1)
+                .unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn shares_subtrees() {
+        // The same voxel in two top-level octants: the root, one branch
+        // shared by both octants and one leaf.
+        let two_voxels = |data| {
+            let mut tree: Octree<u32, u8> = Octree::new(2);
+            tree.merge(AABB::new(0.0, 0.0, 0.0, 0.25, 0.25, 0.25), 1)
+                .unwrap();
+            tree.merge(AABB::new(0.5, 0.0, 0.0, 0.75, 0.25, 0.25), data)
+                .unwrap();
+            OctreeDag::new(&tree)
+        };
+        let dag = two_voxels(1);
+        assert_eq!(dag.node_count(), 3);
+        assert_eq!(dag.compression_ratio(), 5.0 / 3.0);
+        assert_eq!(two_voxels(2).node_count(), 5);
+        assert_eq!(OctreeDag::new(&Octree::<u32, u8>::new(2)).node_count(), 0);
+    }
+
+    #[test]
+    fn queries() {
+        let tree = pillars();
+        let dag = OctreeDag::new(&tree);
+        assert!(dag.node_count() < tree.size());
+        assert_eq!(dag.to_octree().size(), tree.size());
+        for (code, node) in tree.iter() {
+            assert_eq!(dag.lookup(Morton::new(code).unwrap()), Some(&node.data));
+        }
+        assert_eq!(dag.lookup(Morton::root()), None);
+        assert_eq!(dag.leaf_at((2, 5, 0)).map(|(_, data)| *data), Some(1));
+        assert_eq!(dag.leaf_at((1, 5, 0)), None);
+        assert_eq!(
+            dag.leaf_at((6, 7, 0)).map(|(code, _)| code),
+            tree.leaf_at((6, 7, 0)).map(|(code, _)| code)
+        );
+    }
+
+    #[test]
+    fn raycast() {
+        let dag = OctreeDag::new(&pillars());
+        let hit = dag.raycast((1.5, 0.55, 0.0625), (-1.0, 0.0, 0.0)).unwrap();
+        assert_eq!(hit.distance, 0.625);
+        assert_eq!(
+            hit.loc_code,
+            Morton::new(u32::from_coords(6, 4, 0, 3)).unwrap()
+        );
+        assert!(dag.raycast((0.5, 0.5, 0.5), (1.0, 0.0, 0.0)).is_none());
+        assert!(dag.raycast((1.5, 0.5, 0.0625), (1.0, 0.0, 0.0)).is_none());
+    }
+}
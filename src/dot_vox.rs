@@ -6,7 +6,7 @@
 //
 
 use crate::aabb::AABB;
-use crate::{LocCode, Octree};
+use crate::{LocCode, Octree, OctreeError, WorldTransform};
 use dot_vox::{DotVoxData, Model, Voxel};
 use rayon::prelude::*;
 
@@ -30,48 +30,40 @@ impl From<&Voxel> for AABB {
     }
 }
 
-fn voxel_to_aabb(
-    voxel: Voxel,
-    offset: (f64, f64, f64),
-    normalization_vector: (f64, f64, f64),
-    palette: &[u32],
-) -> (AABB, u32) {
-    (
-        AABB::from(&voxel)
-            .offset(offset)
-            .normalize_with(normalization_vector),
-        palette[voxel.i as usize],
-    )
-}
-
 pub(crate) fn model_to_octree<L>(
     model: &Model,
     max_depth: u32,
-    offset: (f64, f64, f64),
-    normalization_vector: (f64, f64, f64),
+    offset: (u32, u32, u32),
+    frame_size: u32,
     palette: &[u32],
-) -> Octree<L, u32>
+) -> Result<Octree<L, u32>, OctreeError>
 where
     L: LocCode,
 {
-    let mut octree = Octree::new(max_depth);
-    model
-        .voxels
-        .iter()
-        .map(|voxel: &Voxel| voxel_to_aabb(*voxel, offset, normalization_vector, palette))
-        .collect::<Vec<(AABB, u32)>>()
-        .into_iter()
-        .for_each(|(aabb, data)| {
-            octree.merge(aabb, data);
-        });
-    octree
+    // Keep the model in its own voxel units, wherever it is framed.
+    let origin = (-(offset.0 as f64), -(offset.1 as f64), -(offset.2 as f64));
+    let frame_depth = frame_size.trailing_zeros();
+    // Voxels fill whole nodes when the tree is finer than the frame, and
+    // share them when it is coarser.
+    let voxel_depth = frame_depth.min(max_depth);
+    let shift = frame_depth - voxel_depth;
+    let voxels = model.voxels.iter().map(|voxel| {
+        (
+            (voxel.x as u64 + offset.0 as u64) >> shift,
+            (voxel.y as u64 + offset.1 as u64) >> shift,
+            (voxel.z as u64 + offset.2 as u64) >> shift,
+            palette[voxel.i as usize],
+        )
+    });
+    Ok(Octree::from_voxels_at(max_depth, voxel_depth, voxels)?
+        .with_world_transform(WorldTransform::new(origin, frame_size as f64)))
 }
 
 pub(crate) fn vox_to_octrees<L>(
     data: DotVoxData,
     max_depth: u32,
     conversion_type: ConversionType,
-) -> Vec<Octree<L, u32>>
+) -> Result<Vec<Octree<L, u32>>, OctreeError>
 where
     L: LocCode,
 {
@@ -79,7 +71,7 @@ where
         .iter()
         .map(|model| {
             let max_size = std::cmp::max(std::cmp::max(model.size.x, model.size.y), model.size.z);
-            let frame_size = 2_f64.powf((max_size as f64).log2().ceil()) as u32;
+            let frame_size = max_size.next_power_of_two();
 
             let offsets = if conversion_type != ConversionType::Default {
                 let mut offsets = vec![];
@@ -98,44 +90,29 @@ where
                     (frame_size - model.size.z) / 2,
                 )]
             };
-            println!(
-                "Model of size: ({}, {}, {}), Framing in: ({}, {}, {})",
-                model.size.x, model.size.y, model.size.z, frame_size, frame_size, frame_size
-            );
             let mut trees = offsets
                 .par_iter()
                 .map(|offset| {
-                    println!("Computing offset: {:?}", offset);
-                    let tree = model_to_octree(
-                        model,
-                        max_depth,
-                        (offset.0 as f64, offset.1 as f64, offset.2 as f64),
-                        (frame_size as f64, frame_size as f64, frame_size as f64),
-                        &data.palette,
-                    );
+                    let tree =
+                        model_to_octree(model, max_depth, *offset, frame_size, &data.palette)?;
                     let size = tree.size();
-                    (tree, size)
+                    Ok((tree, size))
                 })
-                .collect::<Vec<(Octree<L, u32>, usize)>>();
+                .collect::<Result<Vec<(Octree<L, u32>, usize)>, OctreeError>>()?;
             match conversion_type {
                 ConversionType::Optimal => trees.sort_by(|a, b| a.1.cmp(&b.1).reverse()),
-                ConversionType::Worst => trees.sort_by(|a, b| a.1.cmp(&b.1)),
+                ConversionType::Worst => trees.sort_by_key(|a| a.1),
                 _ => (),
             };
-            trees.pop().unwrap().0
+            Ok(trees.pop().unwrap().0)
         })
-        .collect::<Vec<Octree<L, u32>>>()
+        .collect::<Result<Vec<Octree<L, u32>>, OctreeError>>()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
-    fn alive() {
-        assert!(true);
-    }
-
     #[test]
     fn empty() {
         vox_to_octrees::<u32>(
@@ -147,12 +124,48 @@ mod test {
             },
             5,
             ConversionType::Default,
-        );
+        )
+        .unwrap();
     }
 
     #[test]
     fn basic() {
         let vox = dot_vox::load("./examples/monu10.vox").unwrap();
-        let _octrees: Vec<Octree<u32, u32>> = vox_to_octrees(vox, 21, ConversionType::Default);
+        let _octrees: Vec<Octree<u64, u32>> =
+            vox_to_octrees(vox, 21, ConversionType::Default).unwrap();
+    }
+
+    #[test]
+    fn coarse_grid() {
+        let model = Model {
+            size: dot_vox::Size { x: 4, y: 4, z: 4 },
+            voxels: vec![
+                Voxel {
+                    x: 0,
+                    y: 0,
+                    z: 0,
+                    i: 0,
+                },
+                Voxel {
+                    x: 3,
+                    y: 3,
+                    z: 3,
+                    i: 1,
+                },
+            ],
+        };
+        let tree: Octree<u32, u32> = model_to_octree(&model, 1, (0, 0, 0), 4, &[7, 9]).unwrap();
+        assert_eq!(tree.size(), 2);
+        let tree: Octree<u32, u32> = model_to_octree(&model, 3, (0, 0, 0), 4, &[7, 9]).unwrap();
+        assert_eq!(tree.size(), 2);
+        assert_eq!(tree.depth(), 3);
+        let tree: Octree<u32, u32> = model_to_octree(&model, 3, (2, 0, 1), 8, &[7, 9]).unwrap();
+        assert_eq!(tree.leaf_at_world((3.5, 3.5, 3.5)).unwrap().1.data, 9);
+    }
+
+    #[test]
+    fn too_deep() {
+        let vox = dot_vox::load("./examples/monu10.vox").unwrap();
+        assert!(vox_to_octrees::<u32>(vox, 21, ConversionType::Default).is_err());
     }
 }
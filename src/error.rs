@@ -0,0 +1,103 @@
+//
+// error.rs in generic_octree
+// Description:
+// Errors returned by Octree operations.
+//
+
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum OctreeError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// A file or buffer does not hold a valid tree.
+    Decode(String),
+    /// A file extension no loader is known for, `None` if there is none.
+    UnsupportedExtension(Option<String>),
+    /// A depth is deeper than what the tree or its LocCode type can hold.
+    DepthOverflow { depth: u32, max_depth: u32 },
+    /// A file was written for another tree type or format version.
+    Incompatible(String),
+    /// A `.vox` file could not be parsed.
+    VoxParse(String),
+    /// An argument is out of its valid range.
+    InvalidInput(String),
+}
+
+impl fmt::Display for OctreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "i/o error: {}", error),
+            Self::Decode(message) => write!(f, "invalid octree data: {}", message),
+            Self::UnsupportedExtension(Some(extension)) => {
+                write!(f, "unsupported file extension \"{}\"", extension)
+            }
+            Self::UnsupportedExtension(None) => write!(f, "missing file extension"),
+            Self::DepthOverflow { depth, max_depth } => write!(
+                f,
+                "depth {} is deeper than the maximum depth of {}",
+                depth, max_depth
+            ),
+            Self::Incompatible(message) => write!(f, "incompatible octree file: {}", message),
+            Self::VoxParse(message) => write!(f, "invalid .vox file: {}", message),
+            Self::InvalidInput(message) => write!(f, "invalid input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for OctreeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for OctreeError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[cfg(feature = "dot_tree")]
+impl From<bincode::Error> for OctreeError {
+    fn from(error: bincode::Error) -> Self {
+        match *error {
+            // Corrupt or truncated compressed data surfaces as i/o errors.
+            bincode::ErrorKind::Io(error) => match error.kind() {
+                io::ErrorKind::InvalidData
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::UnexpectedEof => Self::Decode(error.to_string()),
+                _ => Self::Io(error),
+            },
+            error => Self::Decode(error.to_string()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "dot_tree"))]
+mod test {
+    use super::*;
+    use crate::Octree;
+
+    #[test]
+    fn corrupt_files() {
+        let directory = std::env::temp_dir().join(format!("error-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("corrupt.tree");
+        let mut contents = crate::format::Header::new::<u32, u8>(3, "").to_bytes();
+        contents.extend_from_slice(b"not an octree");
+        std::fs::write(&path, contents).unwrap();
+        let loaded = Octree::<u32, u8>::load_from_file(&path);
+        assert!(matches!(loaded, Err(OctreeError::Decode(_))));
+        let loaded = Octree::<u32, u8>::load_from_file(directory.join("tree.txt"));
+        assert!(matches!(
+            loaded,
+            Err(OctreeError::UnsupportedExtension(Some(_)))
+        ));
+        let loaded = Octree::<u32, u8>::load_from_file(directory.join("missing.tree"));
+        assert!(matches!(loaded, Err(OctreeError::Io(_))));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
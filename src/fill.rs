@@ -0,0 +1,234 @@
+//
+// fill.rs in generic_octree
+// Description:
+// Flood fill over empty or same-valued regions of an Octree.
+//
+
+use hashbrown::HashSet;
+use std::collections::VecDeque;
+
+use crate::loc_code::octant_axes;
+use crate::{Direction, LocCode, Octree, OctreeNode, OctreeStorage, Orientation};
+
+/// Which cells are considered neighbours while filling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Cells sharing a face, 6 neighbours.
+    Face,
+    /// Cells sharing a face or an edge, 18 neighbours.
+    Edge,
+    /// Cells sharing a face, an edge or a corner, 26 neighbours.
+    Vertex,
+}
+
+impl Connectivity {
+    /// Unit offsets towards every neighbour of a cell.
+    pub fn offsets(self) -> Vec<(i64, i64, i64)> {
+        let mut offsets: Vec<(i64, i64, i64)> =
+            Direction::FACES.iter().map(|face| face.offset()).collect();
+        if self != Self::Face {
+            offsets.extend(Direction::EDGES.iter().map(|edge| edge.offset()));
+        }
+        if self == Self::Vertex {
+            offsets.extend(Orientation::octants().map(Orientation::sign_vector));
+        }
+        offsets
+    }
+}
+
+/// Leaves and internal nodes of a tree, used to split space into cells.
+pub(crate) struct Cells<'a, T: LocCode, D: Send + Sync, S> {
+    tree: &'a Octree<T, D, S>,
+    internal: HashSet<T>,
+}
+
+impl<'a, T, D, S> Cells<'a, T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    pub(crate) fn new(tree: &'a Octree<T, D, S>) -> Self {
+        let internal = tree.ancestors();
+        Self { tree, internal }
+    }
+
+    /// Every empty node whose parent has descendants.
+    pub(crate) fn empty_cells(&self) -> Vec<T> {
+        if self.internal.is_empty() {
+            return if self.tree.size() == 0 {
+                vec![T::root()]
+            } else {
+                vec![]
+            };
+        }
+        self.internal
+            .iter()
+            .flat_map(|code| code.children())
+            .filter(|code| !self.internal.contains(code) && !self.tree.content.contains(code))
+            .collect()
+    }
+
+    /// Find the leaf or the largest empty node containing `loc_code`.
+    fn locate(&self, loc_code: T) -> T {
+        let mut code = T::root();
+        for depth in 1..=loc_code.get_depth() {
+            if !self.internal.contains(&code) {
+                break;
+            }
+            code = loc_code.ancestor_at(depth).unwrap();
+        }
+        code
+    }
+
+    fn data(&self, cell: T) -> Option<D> {
+        self.tree.content.get(&cell).map(|node| node.data)
+    }
+
+    /// Cells lying in `loc_code` that touch the cell at `-offset` from it.
+    fn touching(&self, loc_code: T, offset: (i64, i64, i64), cells: &mut Vec<T>) {
+        let cell = self.locate(loc_code);
+        if cell != loc_code || !self.internal.contains(&cell) {
+            cells.push(cell);
+            return;
+        }
+        for child in loc_code.children() {
+            let axes = octant_axes(child);
+            if touches(axes.0, offset.0) && touches(axes.1, offset.1) && touches(axes.2, offset.2) {
+                self.touching(child, offset, cells);
+            }
+        }
+    }
+}
+
+/// Whether a child on the `axis` side borders the cell at `-offset`.
+fn touches(axis: u64, offset: i64) -> bool {
+    match offset {
+        1 => axis == 0,
+        -1 => axis == 1,
+        _ => true,
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Fill the region connected to the `seed` voxel with `value`.
+    ///
+    /// The seed is given in integer coordinates at `max_depth`. If it lies in
+    /// empty space, the connected empty region is filled, otherwise the
+    /// connected region holding the same data as the seed is replaced.
+    /// Returns the number of nodes that were filled.
+    pub fn flood_fill(
+        &mut self,
+        seed: (u64, u64, u64),
+        value: D,
+        connectivity: Connectivity,
+    ) -> usize {
+        let depth = self.max_depth();
+        let side = 1_u64 << depth;
+        if seed.0 >= side || seed.1 >= side || seed.2 >= side {
+            return 0;
+        }
+
+        let filled = {
+            let cells = Cells::new(self);
+            let start = cells.locate(T::from_coords(seed.0, seed.1, seed.2, depth));
+            let target = cells.data(start);
+            if target == Some(value) {
+                return 0;
+            }
+
+            let offsets = connectivity.offsets();
+            let mut visited = HashSet::new();
+            let mut queue = VecDeque::new();
+            visited.insert(start);
+            queue.push_back(start);
+            let mut neighbours = vec![];
+            while let Some(cell) = queue.pop_front() {
+                let level = cell.get_depth();
+                let side = 1_i64 << level;
+                let (x, y, z) = cell.to_coords();
+                for offset in offsets.iter() {
+                    let (nx, ny, nz) = (
+                        x as i64 + offset.0,
+                        y as i64 + offset.1,
+                        z as i64 + offset.2,
+                    );
+                    if nx < 0 || ny < 0 || nz < 0 || nx >= side || ny >= side || nz >= side {
+                        continue;
+                    }
+                    let code = T::from_coords(nx as u64, ny as u64, nz as u64, level);
+                    cells.touching(code, *offset, &mut neighbours);
+                    for neighbour in neighbours.drain(..) {
+                        if cells.data(neighbour) == target && visited.insert(neighbour) {
+                            queue.push_back(neighbour);
+                        }
+                    }
+                }
+            }
+            visited
+        };
+
+        let count = filled.len();
+        let parents = filled
+            .into_iter()
+            .filter_map(|cell| {
+                self.content.insert(cell, OctreeNode::new(value));
+                cell.parent()
+            })
+            .collect::<HashSet<T>>();
+        self.collapse(parents.into_iter().collect());
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Morton, AABB};
+
+    #[test]
+    fn fill_empty_tree() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        assert_eq!(tree.flood_fill((1, 2, 3), 1, Connectivity::Face), 1);
+        assert_eq!(tree.lookup(Morton::root()).map(|node| node.data), Some(1));
+    }
+
+    #[test]
+    fn fill_stops_at_walls() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.5, 0.0, 0.0, 0.625, 1.0, 1.0), 1)
+            .unwrap();
+        tree.flood_fill((0, 0, 0), 2, Connectivity::Vertex);
+        let left = u32::from_coords(3, 7, 7, 3);
+        let right = u32::from_coords(5, 0, 0, 3);
+        assert_eq!(
+            tree.lookup(Morton::new(left >> 6).unwrap())
+                .map(|node| node.data),
+            Some(2)
+        );
+        assert!(tree.lookup(Morton::new(right).unwrap()).is_none());
+        assert!(tree.lookup(Morton::new(right >> 3).unwrap()).is_none());
+    }
+
+    #[test]
+    fn fill_same_value() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 1.0, 1.0, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.0, 0.0, 0.5, 1.0, 0.5, 1.0), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.0, 0.5, 0.5, 0.5, 1.0, 1.0), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.5, 0.5, 0.5, 1.0, 1.0, 1.0), 3)
+            .unwrap();
+        tree.flood_fill((0, 0, 0), 2, Connectivity::Face);
+        let mut datas: Vec<u8> = tree.iter().map(|(_, node)| node.data).collect();
+        datas.sort();
+        assert_eq!(datas, vec![2, 2, 2, 2, 2, 2, 2, 3]);
+    }
+}
@@ -0,0 +1,270 @@
+//
+// format.rs in generic_octree
+// Description:
+// Header of `.tree` files and migration of files without one.
+//
+
+use flate2::read::ZlibDecoder;
+use hashbrown::HashMap;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::mem::size_of;
+use std::path::Path;
+
+use crate::{LocCode, Morton, Octree, OctreeError, OctreeNode, OctreeStorage};
+
+const MAGIC: [u8; 4] = *b"OCTR";
+const VERSION: u32 = 1;
+/// Longest payload tag accepted, so a corrupt length cannot make readers
+/// allocate gigabytes.
+const MAX_TAG: usize = 4096;
+
+/// Uncompressed header of a `.tree` file, all fields little-endian: the
+/// magic, the version, the LocCode width, the payload size, `max_depth` then
+/// the length and bytes of the payload tag.
+///
+/// The tag is chosen by the writer, empty by default, and only checked by
+/// readers asking for one, as type names are not stable across compilers.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Header {
+    version: u32,
+    code_width: u32,
+    payload_size: u32,
+    max_depth: u32,
+    payload_tag: String,
+}
+
+impl Header {
+    pub(crate) fn new<L, D>(max_depth: u32, payload_tag: &str) -> Self {
+        Self {
+            version: VERSION,
+            code_width: size_of::<L>() as u32,
+            payload_size: size_of::<D>() as u32,
+            max_depth,
+            payload_tag: payload_tag.to_string(),
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for field in &[
+            self.version,
+            self.code_width,
+            self.payload_size,
+            self.max_depth,
+            self.payload_tag.len() as u32,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(self.payload_tag.as_bytes());
+        bytes
+    }
+
+    /// Read the header at the start of `reader`, `None` if it does not
+    /// start with one.
+    pub(crate) fn read<R: Read>(reader: &mut R) -> Result<Option<Self>, OctreeError> {
+        let mut magic = [0; 4];
+        read_header_bytes(reader, &mut magic)?;
+        if magic != MAGIC {
+            return Ok(None);
+        }
+        let mut fields = [0; 20];
+        read_header_bytes(reader, &mut fields)?;
+        let field =
+            |index: usize| u32::from_le_bytes(fields[4 * index..4 * index + 4].try_into().unwrap());
+        if field(0) != VERSION {
+            return Err(OctreeError::Incompatible(format!(
+                "format version {} is not supported",
+                field(0)
+            )));
+        }
+        if field(4) as usize > MAX_TAG {
+            return Err(OctreeError::Decode("payload tag too long".to_string()));
+        }
+        let mut payload_tag = vec![0; field(4) as usize];
+        read_header_bytes(reader, &mut payload_tag)?;
+        let payload_tag = String::from_utf8(payload_tag)
+            .map_err(|_| OctreeError::Decode("payload tag is not UTF-8".to_string()))?;
+        Ok(Some(Self {
+            version: field(0),
+            code_width: field(1),
+            payload_size: field(2),
+            max_depth: field(3),
+            payload_tag,
+        }))
+    }
+
+    /// Check that the file can be loaded as a tree of `L` and `D`, and that
+    /// it has the given payload tag if any.
+    pub(crate) fn check<L: LocCode, D>(
+        &self,
+        payload_tag: Option<&str>,
+    ) -> Result<(), OctreeError> {
+        let expected = Self::new::<L, D>(self.max_depth, payload_tag.unwrap_or_default());
+        if self.code_width != expected.code_width {
+            return Err(OctreeError::Incompatible(format!(
+                "file has {} bit LocCodes, not {} bit",
+                8 * self.code_width,
+                8 * expected.code_width
+            )));
+        }
+        if self.payload_size != expected.payload_size {
+            return Err(OctreeError::Incompatible(format!(
+                "file holds payloads of {} bytes, not {}",
+                self.payload_size, expected.payload_size
+            )));
+        }
+        if payload_tag.is_some_and(|tag| tag != self.payload_tag) {
+            return Err(OctreeError::Incompatible(format!(
+                "file holds \"{}\" payloads, not \"{}\"",
+                self.payload_tag, expected.payload_tag
+            )));
+        }
+        if self.max_depth > L::MAX_LEVEL {
+            return Err(OctreeError::DepthOverflow {
+                depth: self.max_depth,
+                max_depth: L::MAX_LEVEL,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+}
+
+fn read_header_bytes<R: Read>(reader: &mut R, bytes: &mut [u8]) -> Result<(), OctreeError> {
+    reader
+        .read_exact(bytes)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => OctreeError::Decode("truncated header".to_string()),
+            _ => OctreeError::Io(error),
+        })
+}
+
+/// Layout of the trees written by versions without a header.
+#[derive(Deserialize)]
+struct LegacyOctree<L: LocCode, D> {
+    content: HashMap<L, OctreeNode<D>>,
+    max_depth: u32,
+}
+
+impl<L, D, S> Octree<L, D, S>
+where
+    L: LocCode + DeserializeOwned,
+    D: Copy + PartialEq + Send + Sync + DeserializeOwned,
+    S: OctreeStorage<L, D>,
+{
+    /// Load a `.tree` file written by a version without a header, which
+    /// cannot be checked against `L` and `D`.
+    pub fn load_legacy<P: AsRef<Path>>(path: P) -> Result<Self, OctreeError> {
+        let reader = ZlibDecoder::new(BufReader::new(File::open(path)?));
+        let legacy: LegacyOctree<L, D> = bincode::deserialize_from(reader)?;
+        let mut tree = Self::try_new(legacy.max_depth)?;
+        for (loc_code, node) in legacy.content {
+            let loc_code = Morton::decode(loc_code, legacy.max_depth)?.get();
+            let loc_code = Morton::from_legacy(loc_code).expect("code was just checked");
+            tree.content.insert(loc_code.get(), node);
+        }
+        Ok(tree)
+    }
+}
+
+impl<L, D, S> Octree<L, D, S>
+where
+    L: LocCode + serde::Serialize + DeserializeOwned,
+    D: Copy + PartialEq + Send + Sync + serde::Serialize + DeserializeOwned,
+    S: OctreeStorage<L, D> + serde::Serialize + DeserializeOwned,
+{
+    /// Rewrite a `.tree` file written by a version without a header in the
+    /// current format, returning the migrated tree.
+    ///
+    /// Files which already have a header are loaded and left untouched.
+    pub fn migrate_file<P: AsRef<Path>>(path: P) -> Result<Self, OctreeError> {
+        let path = path.as_ref();
+        if Header::read(&mut File::open(path)?)?.is_some() {
+            return Self::load_from_file(path);
+        }
+        let tree = Self::load_legacy(path)?;
+        tree.save_to_file(path)?;
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Morton, AABB};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn header_checks() {
+        let directory = std::env::temp_dir().join(format!("format-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("tree.tree");
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 1.0, 1.0), 4)
+            .unwrap();
+        tree.save_to_file(&path).unwrap();
+        let loaded = Octree::<u32, u8>::load_from_file(&path).unwrap();
+        assert_eq!(loaded.size(), tree.size());
+        let wrong_code = Octree::<u64, u8>::load_from_file(&path);
+        assert!(matches!(wrong_code, Err(OctreeError::Incompatible(_))));
+        let wrong_data = Octree::<u32, u16>::load_from_file(&path);
+        assert!(matches!(wrong_data, Err(OctreeError::Incompatible(_))));
+        std::fs::remove_dir_all(directory).unwrap();
+
+        let mut buffer = vec![];
+        tree.write_to_tagged(&mut buffer, "material").unwrap();
+        assert!(Octree::<u32, u8>::read_from(buffer.as_slice()).is_ok());
+        assert!(Octree::<u32, u8>::read_from_tagged(buffer.as_slice(), "material").is_ok());
+        let wrong_tag = Octree::<u32, u8>::read_from_tagged(buffer.as_slice(), "density");
+        assert!(matches!(wrong_tag, Err(OctreeError::Incompatible(_))));
+    }
+
+    #[test]
+    fn migrate_legacy() {
+        let directory = std::env::temp_dir().join(format!("legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("legacy.tree");
+        // Old octant 3 is the lowest corner, old octant 1 the one above it
+        // and in front.
+        let mut content = HashMap::new();
+        content.insert(0b1_011_001_u32, OctreeNode::new(7_u8));
+        content.insert(0b1_100_u32, OctreeNode::new(9_u8));
+        let binary = bincode::serialize(&(content, 2_u32)).unwrap();
+        let mut encoder = ZlibEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(&binary).unwrap();
+        encoder.finish().unwrap();
+        assert!(matches!(
+            Octree::<u32, u8>::load_from_file(&path),
+            Err(OctreeError::Incompatible(_))
+        ));
+
+        let tree = Octree::<u32, u8>::migrate_file(&path).unwrap();
+        let loaded = Octree::<u32, u8>::load_from_file(&path).unwrap();
+        for tree in &[tree, loaded] {
+            assert_eq!(tree.size(), 2);
+            let near = Morton::new(u32::from_coords(0, 1, 1, 2)).unwrap();
+            let right = Morton::new(u32::from_coords(1, 0, 0, 1)).unwrap();
+            assert_eq!(tree.lookup(near).map(|node| node.data), Some(7));
+            assert_eq!(tree.lookup(right).map(|node| node.data), Some(9));
+        }
+
+        let mut content = HashMap::new();
+        content.insert(0_u32, OctreeNode::new(7_u8));
+        let binary = bincode::serialize(&(content, 2_u32)).unwrap();
+        let mut encoder = ZlibEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(&binary).unwrap();
+        encoder.finish().unwrap();
+        assert!(matches!(
+            Octree::<u32, u8>::load_legacy(&path),
+            Err(OctreeError::Decode(_))
+        ));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//
+// gpu.rs in generic_octree
+// Description:
+// Flat breadth-first node buffer for ray marching octrees on the GPU.
+//
+
+use std::collections::VecDeque;
+
+use crate::dag::Ray;
+use crate::{LocCode, Octree, OctreeStorage};
+
+/// Number of `u32` words describing a node in `GpuBuffer::nodes`.
+pub const NODE_WORDS: usize = 3;
+
+/// Tree laid out as flat arrays that can be uploaded as storage buffers.
+///
+/// Nodes are stored breadth-first, `NODE_WORDS` words each:
+/// - word 0 holds the valid mask in bits 0-7, one bit per existing child,
+///   and the leaf mask in bits 8-15, one bit per child that is a leaf;
+/// - word 1 is the index of the node of the first non-leaf child, the other
+///   ones following in octant order;
+/// - word 2 is the index in `payloads` of the data of the first leaf child,
+///   the other ones following in octant order.
+///
+/// Octants are indexed by their axis bits, `4` for x, `2` for y and `1` for
+/// z, and node 0 is the root. A tree made of a single root leaf is stored as
+/// a root with 8 identical leaves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuBuffer<D> {
+    pub nodes: Vec<u32>,
+    pub payloads: Vec<D>,
+}
+
+/// Index of the `octant` bit among the set bits of `mask`.
+fn rank(mask: u32, octant: usize) -> u32 {
+    (mask & ((1 << octant) - 1)).count_ones()
+}
+
+impl<D> GpuBuffer<D> {
+    /// Number of nodes in the buffer.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() / NODE_WORDS
+    }
+
+    fn node(&self, index: usize) -> (u32, u32, u32, u32) {
+        let words = &self.nodes[index * NODE_WORDS..(index + 1) * NODE_WORDS];
+        (words[0] & 0xff, (words[0] >> 8) & 0xff, words[1], words[2])
+    }
+
+    /// Reference traversal: data of the leaf containing a point of the unit
+    /// cube, walking the buffer as a shader would.
+    pub fn sample(&self, point: (f64, f64, f64)) -> Option<&D> {
+        let point = [point.0, point.1, point.2];
+        if point.iter().any(|axis| !(0.0..1.0).contains(axis)) || self.nodes.is_empty() {
+            return None;
+        }
+        let (mut index, mut min, mut size) = (0, [0.0; 3], 1.0);
+        loop {
+            let (valid, leaf, first_child, first_payload) = self.node(index);
+            size /= 2.0;
+            let mut octant = 0;
+            for (axis, bit) in [4, 2, 1].iter().enumerate() {
+                if point[axis] >= min[axis] + size {
+                    octant |= bit;
+                    min[axis] += size;
+                }
+            }
+            if valid & (1 << octant) == 0 {
+                return None;
+            }
+            if leaf & (1 << octant) != 0 {
+                return self
+                    .payloads
+                    .get((first_payload + rank(leaf, octant)) as usize);
+            }
+            index = (first_child + rank(valid & !leaf, octant)) as usize;
+        }
+    }
+
+    /// Reference traversal: distance and data of the first leaf hit by the
+    /// ray `origin + t * direction` for `t >= 0`, in the unit cube.
+    pub fn raycast(
+        &self,
+        origin: (f64, f64, f64),
+        direction: (f64, f64, f64),
+    ) -> Option<(f64, &D)> {
+        let ray = Ray {
+            origin: [origin.0, origin.1, origin.2],
+            direction: [direction.0, direction.1, direction.2],
+        };
+        ray.enter([0.0; 3], 1.0)?;
+        if self.nodes.is_empty() {
+            return None;
+        }
+        self.cast(&ray, 0, [0.0; 3], 1.0)
+    }
+
+    fn cast(&self, ray: &Ray, index: usize, min: [f64; 3], size: f64) -> Option<(f64, &D)> {
+        let (valid, leaf, first_child, first_payload) = self.node(index);
+        let half = size / 2.0;
+        let mut hits = vec![];
+        for octant in (0..8).filter(|octant| valid & (1 << octant) != 0) {
+            let corner =
+                |bit: usize, axis: usize| min[axis] + if octant & bit != 0 { half } else { 0.0 };
+            let min = [corner(4, 0), corner(2, 1), corner(1, 2)];
+            if let Some(distance) = ray.enter(min, half) {
+                hits.push((distance, octant, min));
+            }
+        }
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        hits.into_iter().find_map(|(distance, octant, min)| {
+            if leaf & (1 << octant) != 0 {
+                let payload = (first_payload + rank(leaf, octant)) as usize;
+                self.payloads.get(payload).map(|data| (distance, data))
+            } else {
+                let child = (first_child + rank(valid & !leaf, octant)) as usize;
+                self.cast(ray, child, min, half)
+            }
+        })
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Lay the tree out as a `GpuBuffer`.
+    pub fn to_gpu_buffer(&self) -> GpuBuffer<D> {
+        let mut buffer = GpuBuffer {
+            nodes: vec![],
+            payloads: vec![],
+        };
+        if let Some(root) = self.content.get(&T::root()) {
+            buffer.nodes.extend_from_slice(&[0xffff, 1, 0]);
+            buffer.payloads.extend_from_slice(&[root.data; 8]);
+            return buffer;
+        }
+        let internal = self.ancestors();
+        let mut queue = VecDeque::new();
+        queue.push_back(T::root());
+        let mut next_node = 1;
+        while let Some(code) = queue.pop_front() {
+            let (mut valid, mut leaf) = (0, 0);
+            let first_payload = buffer.payloads.len() as u32;
+            for (octant, child) in code.children().enumerate() {
+                if let Some(node) = self.content.get(&child) {
+                    valid |= 1 << octant;
+                    leaf |= 1 << octant;
+                    buffer.payloads.push(node.data);
+                } else if internal.contains(&child) {
+                    valid |= 1 << octant;
+                    queue.push_back(child);
+                }
+            }
+            buffer
+                .nodes
+                .extend_from_slice(&[valid | leaf << 8, next_node, first_payload]);
+            next_node += (valid & !leaf).count_ones();
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    #[test]
+    fn matches_tree() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.625, 0.75, 0.125, 1.0, 0.875, 0.5), 2)
+            .unwrap();
+        let buffer = tree.to_gpu_buffer();
+        assert_eq!(buffer.nodes.len() % NODE_WORDS, 0);
+        assert_eq!(buffer.payloads.len(), tree.size());
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    let point = (
+                        (x as f64 + 0.5) / 8.0,
+                        (y as f64 + 0.5) / 8.0,
+                        (z as f64 + 0.5) / 8.0,
+                    );
+                    let expected = tree.leaf_at((x, y, z)).map(|(_, node)| &node.data);
+                    assert_eq!(buffer.sample(point), expected);
+                }
+            }
+        }
+        let (distance, data) = buffer.raycast((0.9, 0.8, -1.0), (0.0, 0.0, 1.0)).unwrap();
+        assert_eq!((distance, *data), (1.125, 2));
+        assert!(buffer.raycast((0.9, 0.6, -1.0), (0.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn root_leaf() {
+        let mut tree: Octree<u32, u8> = Octree::new(2);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 1.0, 1.0, 1.0), 7)
+            .unwrap();
+        let buffer = tree.to_gpu_buffer();
+        assert_eq!(buffer.node_count(), 1);
+        assert_eq!(buffer.sample((0.9, 0.1, 0.5)), Some(&7));
+        let empty: Octree<u32, u8> = Octree::new(2);
+        assert_eq!(empty.to_gpu_buffer().sample((0.5, 0.5, 0.5)), None);
+    }
+}
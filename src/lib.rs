@@ -2,10 +2,25 @@
 
 pub mod aabb;
 
+mod bulk;
+mod concurrent;
+mod dag;
+mod error;
+mod fill;
+mod gpu;
+mod linear_storage;
 mod loc_code;
+mod morphology;
+mod morton;
 mod node;
 mod octree;
 mod orientation;
+mod section;
+mod shared_storage;
+mod stats;
+mod storage;
+mod transform;
+mod voxel_box;
 
 #[cfg(feature = "render")]
 pub mod render;
@@ -13,11 +28,40 @@ pub mod render;
 #[cfg(feature = "vox")]
 pub mod dot_vox;
 
-pub use aabb::{Plane, AABB};
-pub use loc_code::LocCode;
+#[cfg(feature = "dot_tree")]
+mod format;
+
+#[cfg(feature = "mmap")]
+mod mapped;
+
+#[cfg(feature = "dot_tree")]
+mod world;
+
+pub use aabb::{Plane, PlaneAxis, AABB};
+pub use concurrent::ConcurrentOctree;
+pub use dag::{OctreeDag, RayHit};
+pub use error::OctreeError;
+pub use fill::Connectivity;
+pub use gpu::{GpuBuffer, NODE_WORDS};
+pub use linear_storage::{LinearNodes, LinearOctree, LinearStorage};
+pub use loc_code::{Children, LocCode};
+pub use morphology::{Propagation, StructuringElement};
+pub use morton::{InvalidMorton, Morton, ParseMortonError};
 pub use node::OctreeNode;
 pub use octree::Octree;
-pub use orientation::Orientation;
+pub use orientation::{Direction, InvalidOctant, Orientation};
+pub use section::CrossSection;
+pub use shared_storage::{NodeChange, SharedNodes, SharedOctree, SharedStorage};
+pub use stats::OctreeStats;
+pub use storage::{HashMapNodes, HashMapRange, OctreeStorage};
+pub use transform::WorldTransform;
+pub use voxel_box::VoxelBox;
 
 #[cfg(feature = "render")]
 pub use render::{Model, Vertex};
+
+#[cfg(feature = "mmap")]
+pub use mapped::{MappedOctree, Pod};
+
+#[cfg(feature = "dot_tree")]
+pub use world::{ChunkKey, OctreeWorld};
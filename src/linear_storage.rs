@@ -0,0 +1,239 @@
+//
+// linear_storage.rs in generic_octree
+// Description:
+// Sorted vector of nodes in Morton order.
+//
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use hashbrown::HashMap;
+use std::iter::FromIterator;
+
+use crate::{LocCode, Octree, OctreeNode, OctreeStorage};
+
+/// Storage keeping the nodes in a vector sorted in Morton order, compact and
+/// cache friendly for read-mostly trees.
+///
+/// Lookups are binary searches and every subtree is a contiguous slice, but
+/// inserting or removing a node moves the ones after it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct LinearStorage<L, D> {
+    nodes: Vec<(L, OctreeNode<D>)>,
+}
+
+/// Octree stored in a `LinearStorage`.
+pub type LinearOctree<L, D> = Octree<L, D, LinearStorage<L, D>>;
+
+/// Position of a code in Morton order: the code aligned to the deepest
+/// level, ancestors coming before the first of their descendants.
+pub(crate) fn morton_key<L: LocCode>(loc_code: L) -> (u128, u32) {
+    let depth = loc_code.get_depth();
+    let raw: u128 = loc_code.into();
+    (raw << (3 * (L::MAX_LEVEL - depth)), depth)
+}
+
+/// Sort `nodes` with the stable `sort` then drop duplicated codes, the last
+/// node given for a code winning.
+pub(crate) fn sort_keep_last<L, T, F>(nodes: &mut Vec<(L, T)>, sort: F)
+where
+    L: PartialEq + Copy,
+    F: FnOnce(&mut [(L, T)]),
+{
+    // The sort is stable, so reversing first keeps the last duplicate.
+    nodes.reverse();
+    sort(nodes);
+    nodes.dedup_by_key(|(loc_code, _)| *loc_code);
+}
+
+impl<L: LocCode, D> LinearStorage<L, D> {
+    /// Sort nodes given in any order, the last one winning for duplicated
+    /// codes.
+    pub fn from_unsorted<I: IntoIterator<Item = (L, OctreeNode<D>)>>(nodes: I) -> Self {
+        let mut nodes: Vec<(L, OctreeNode<D>)> = nodes.into_iter().collect();
+        sort_keep_last(&mut nodes, |nodes| {
+            nodes.sort_by_key(|(loc_code, _)| morton_key(*loc_code))
+        });
+        Self { nodes }
+    }
+
+    /// Nodes in Morton order.
+    pub fn as_slice(&self) -> &[(L, OctreeNode<D>)] {
+        &self.nodes
+    }
+
+    fn search(&self, loc_code: &L) -> Result<usize, usize> {
+        let key = morton_key(*loc_code);
+        self.nodes
+            .binary_search_by_key(&key, |(code, _)| morton_key(*code))
+    }
+
+    /// Nodes of the subtree of `root`, itself included, found in O(log n).
+    pub fn subtree(&self, root: L) -> &[(L, OctreeNode<D>)] {
+        let (start, depth) = morton_key(root);
+        let end = start | ((1_u128 << (3 * (L::MAX_LEVEL - depth))) - 1);
+        let first = self
+            .nodes
+            .partition_point(|(code, _)| morton_key(*code) < (start, depth));
+        let last = self
+            .nodes
+            .partition_point(|(code, _)| morton_key(*code) <= (end, L::MAX_LEVEL));
+        &self.nodes[first..last]
+    }
+}
+
+impl<L, D> Default for LinearStorage<L, D> {
+    fn default() -> Self {
+        Self { nodes: vec![] }
+    }
+}
+
+impl<L: LocCode, D> FromIterator<(L, OctreeNode<D>)> for LinearStorage<L, D> {
+    fn from_iter<I: IntoIterator<Item = (L, OctreeNode<D>)>>(nodes: I) -> Self {
+        Self::from_unsorted(nodes)
+    }
+}
+
+impl<L: LocCode, D> OctreeStorage<L, D> for LinearStorage<L, D> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn get(&self, loc_code: &L) -> Option<&OctreeNode<D>> {
+        self.search(loc_code).ok().map(|index| &self.nodes[index].1)
+    }
+
+    fn insert(&mut self, loc_code: L, node: OctreeNode<D>) -> Option<OctreeNode<D>> {
+        match self.search(&loc_code) {
+            Ok(index) => Some(std::mem::replace(&mut self.nodes[index].1, node)),
+            Err(index) => {
+                self.nodes.insert(index, (loc_code, node));
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, loc_code: &L) -> Option<OctreeNode<D>> {
+        self.search(loc_code)
+            .ok()
+            .map(|index| self.nodes.remove(index).1)
+    }
+
+    type Iter<'a>
+        = LinearNodes<'a, L, D>
+    where
+        Self: 'a;
+
+    type Range<'a>
+        = LinearNodes<'a, L, D>
+    where
+        Self: 'a;
+
+    /// Iterate over the nodes in Morton order.
+    fn iter(&self) -> Self::Iter<'_> {
+        LinearNodes(self.nodes.iter())
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<(L, OctreeNode<D>)>()
+    }
+
+    fn range(&self, root: L) -> Self::Range<'_> {
+        LinearNodes(self.subtree(root).iter())
+    }
+}
+
+/// Nodes of a `LinearStorage`, in Morton order.
+#[derive(Debug, Clone)]
+pub struct LinearNodes<'a, L, D>(std::slice::Iter<'a, (L, OctreeNode<D>)>);
+
+impl<'a, L: Copy, D> Iterator for LinearNodes<'a, L, D> {
+    type Item = (L, &'a OctreeNode<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(loc_code, node)| (*loc_code, node))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<L, D> From<Octree<L, D>> for LinearOctree<L, D>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    fn from(tree: Octree<L, D>) -> Self {
+        tree.convert_storage()
+    }
+}
+
+impl<L, D> From<LinearOctree<L, D>> for Octree<L, D, HashMap<L, OctreeNode<D>>>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    fn from(tree: LinearOctree<L, D>) -> Self {
+        tree.convert_storage()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Morton, AABB};
+
+    #[test]
+    fn morton_order() {
+        let deep = u32::from_coords(1, 1, 1, 2);
+        let storage: LinearStorage<u32, u8> = vec![
+            (u32::from_coords(1, 1, 1, 1), OctreeNode::new(1)),
+            (deep, OctreeNode::new(2)),
+            (u32::from_coords(0, 0, 0, 1), OctreeNode::new(3)),
+            (deep, OctreeNode::new(4)),
+        ]
+        .into_iter()
+        .collect();
+        let codes: Vec<u32> = storage.iter().map(|(code, _)| code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                u32::from_coords(0, 0, 0, 1),
+                deep,
+                u32::from_coords(1, 1, 1, 1)
+            ]
+        );
+        assert_eq!(storage.get(&deep).map(|node| node.data), Some(4));
+        assert_eq!(storage.subtree(u32::from_coords(0, 0, 0, 1)).len(), 2);
+        assert_eq!(storage.subtree(u32::from_coords(1, 0, 0, 1)).len(), 0);
+        assert_eq!(storage.subtree(deep).len(), 1);
+    }
+
+    #[test]
+    fn conversion() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.25, 0.5, 0.75, 1.0, 0.625), 1)
+            .unwrap();
+        let mut linear = LinearOctree::from(tree.clone());
+        assert_eq!(linear.size(), tree.size());
+        for (code, node) in tree.iter() {
+            let code = Morton::new(code).unwrap();
+            assert_eq!(linear.lookup(code).map(|node| node.data), Some(node.data));
+        }
+        linear
+            .merge(AABB::new(0.75, 0.0, 0.0, 1.0, 0.25, 0.25), 2)
+            .unwrap();
+        let back: Octree<u32, u8> = Octree::from(linear);
+        assert_eq!(back.size(), tree.size() + 1);
+        let corner = Morton::new(u32::from_coords(3, 0, 0, 2)).unwrap();
+        assert_eq!(back.lookup(corner).map(|node| node.data), Some(2));
+    }
+}
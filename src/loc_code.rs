@@ -1,12 +1,11 @@
 use std::{
+    convert::TryFrom,
     fmt::Debug,
     hash::Hash,
     ops::{BitOr, Shl, Shr},
 };
 
-use crate::Orientation;
-use hashbrown::HashMap;
-use std::sync::Mutex;
+use crate::{Direction, Orientation};
 
 pub trait LocCode:
     Copy
@@ -23,9 +22,12 @@ pub trait LocCode:
     + Shr<Output = Self>
     + BitOr<Output = Self>
     + From<u8>
-    + Into<u64>
+    + Into<u128>
     + BitOr<Orientation, Output = Self>
 {
+    /// Deepest depth, the root being at depth 0, a code of this type can hold.
+    const MAX_LEVEL: u32;
+
     /// Useful for many puproses
     fn zero() -> Self;
 
@@ -45,6 +47,147 @@ pub trait LocCode:
     fn get_offset(self) -> u32;
 
     fn get_center_u32(self) -> (u32, u32, u32);
+
+    /// Code of the cell at integer coordinates `(x, y, z)` of the `2^depth`
+    /// grid, the root being at depth 0.
+    ///
+    /// `depth` must be at most `MAX_LEVEL` and every coordinate below
+    /// `2^depth`. This is only checked by debug assertions: otherwise the
+    /// high bits are dropped and the code names another cell.
+    fn from_coords(x: u64, y: u64, z: u64, depth: u32) -> Self;
+
+    /// Integer coordinates of the cell in the grid of its own depth.
+    fn to_coords(self) -> (u64, u64, u64);
+
+    /// Code of a child, `None` if it would not fit in this type.
+    fn checked_child(self, octant: Orientation) -> Option<Self> {
+        if self.get_level() > Self::MAX_LEVEL {
+            None
+        } else {
+            Some((self << Self::three()) | octant)
+        }
+    }
+
+    /// Depth of the node, the root being at depth 0.
+    fn get_depth(self) -> u32 {
+        self.get_level() - 1
+    }
+
+    /// Code of the parent node, `None` for the root.
+    fn parent(self) -> Option<Self> {
+        if self > Self::root() {
+            Some(self >> Self::three())
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the 8 children, in octant order.
+    /// Nothing is yielded if they would not fit in this type.
+    fn children(self) -> Children<Self> {
+        Children {
+            parent: self,
+            next: if self.get_level() > Self::MAX_LEVEL {
+                8
+            } else {
+                0
+            },
+            skip: None,
+        }
+    }
+
+    /// Iterate over the 7 other children of the parent, none for the root.
+    fn siblings(self) -> Children<Self> {
+        match self.parent() {
+            Some(parent) => Children {
+                parent,
+                next: 0,
+                skip: Some(self),
+            },
+            None => Children {
+                parent: self,
+                next: 8,
+                skip: None,
+            },
+        }
+    }
+
+    /// Ancestor at the given depth, the node itself at its own depth and
+    /// `None` if it is deeper.
+    fn ancestor_at(self, depth: u32) -> Option<Self> {
+        let own = self.get_depth();
+        if depth > own {
+            None
+        } else {
+            Some(self >> Self::from((3 * (own - depth)) as u8))
+        }
+    }
+
+    /// Whether `other` is a strict descendant of this node.
+    fn is_ancestor_of(self, other: Self) -> bool {
+        let depth = self.get_depth();
+        depth < other.get_depth() && other.ancestor_at(depth) == Some(self)
+    }
+
+    /// Deepest node having both `a` and `b` as descendants or being one of them.
+    fn common_ancestor(a: Self, b: Self) -> Self {
+        let depth = std::cmp::min(a.get_depth(), b.get_depth());
+        let (a, b) = (a.ancestor_at(depth).unwrap(), b.ancestor_at(depth).unwrap());
+        let difference: u128 = a.into() ^ b.into();
+        if difference == 0 {
+            return a;
+        }
+        let levels = (127 - difference.leading_zeros()) / 3 + 1;
+        a >> Self::from((3 * levels) as u8)
+    }
+
+    /// Octant of the node inside its parent, `None` for the root.
+    fn octant(self) -> Option<Orientation> {
+        if self == Self::root() {
+            return None;
+        }
+        let raw: u128 = self.into();
+        Orientation::try_from((raw & 7) as u8).ok()
+    }
+
+    /// Node of the same depth across a face or an edge, `None` if it would
+    /// be outside of the root cube.
+    fn neighbor(self, direction: Direction) -> Option<Self> {
+        let depth = self.get_depth();
+        let side = 1_i64 << depth;
+        let (x, y, z) = self.to_coords();
+        let (dx, dy, dz) = direction.offset();
+        let (x, y, z) = (x as i64 + dx, y as i64 + dy, z as i64 + dz);
+        if [x, y, z].iter().all(|axis| (0..side).contains(axis)) {
+            Some(Self::from_coords(x as u64, y as u64, z as u64, depth))
+        } else {
+            None
+        }
+    }
+}
+
+/// Iterator over the children of a node, see `LocCode::children` and
+/// `LocCode::siblings`.
+#[derive(Debug, Clone)]
+pub struct Children<L> {
+    parent: L,
+    next: u8,
+    skip: Option<L>,
+}
+
+impl<L: LocCode> Iterator for Children<L> {
+    type Item = L;
+
+    fn next(&mut self) -> Option<L> {
+        while self.next < 8 {
+            let child = (self.parent << L::three()) | L::from(self.next);
+            self.next += 1;
+            if self.skip != Some(child) {
+                return Some(child);
+            }
+        }
+        None
+    }
 }
 
 macro_rules! impl_loc_code_num {
@@ -53,21 +196,13 @@ macro_rules! impl_loc_code_num {
             type Output = Self;
 
             fn bitor(self, rhs: Orientation) -> Self {
-                match rhs {
-                    Orientation::LBU => self | 0,
-                    Orientation::LFU => self | 1,
-                    Orientation::LFD => self | 2,
-                    Orientation::LBD => self | 3,
-                    Orientation::RBD => self | 4,
-                    Orientation::RFD => self | 5,
-                    Orientation::RFU => self | 6,
-                    Orientation::RBU => self | 7,
-                    _ => self | 8,
-                }
+                self | u8::from(rhs) as Self
             }
         }
 
         impl LocCode for $ty {
+            const MAX_LEVEL: u32 = ((std::mem::size_of::<Self>() * 8 - 1) / 3) as u32;
+
             fn root() -> Self {
                 Self::one()
             }
@@ -88,37 +223,38 @@ macro_rules! impl_loc_code_num {
                 3 as Self
             }
 
-            fn get_level(mut self) -> u32 {
-                let mut level = 1;
-                while self != 1 {
-                    self >>= 3;
-                    level += 1;
-                }
-                level as u32
+            fn get_level(self) -> u32 {
+                let bits = (std::mem::size_of::<Self>() * 8) as u32;
+                (bits - 1 - self.leading_zeros()) / 3 + 1
             }
 
             fn get_offset(self) -> u32 {
-                2u32.pow(32 - self.get_level())
+                ((1_u64 << 32) >> self.get_level()) as u32
             }
 
             fn get_center_u32(self) -> (u32, u32, u32) {
-                if self == 1 {
-                    return (i32::MAX as u32, i32::MAX as u32, i32::MAX as u32);
-                }
-                let offset = 2u32.pow(32 - self.get_level());
-                let mask = Self::MAX ^ 7;
-                let center = (self >> 3).get_center_u32();
-                match (self ^ mask) & !mask {
-                    0 => (center.0 - offset, center.1 + offset, center.2 - offset),
-                    1 => (center.0 - offset, center.1 + offset, center.2 + offset),
-                    2 => (center.0 - offset, center.1 - offset, center.2 + offset),
-                    3 => (center.0 - offset, center.1 - offset, center.2 - offset),
-                    4 => (center.0 + offset, center.1 - offset, center.2 - offset),
-                    5 => (center.0 + offset, center.1 - offset, center.2 + offset),
-                    6 => (center.0 + offset, center.1 + offset, center.2 + offset),
-                    7 => (center.0 + offset, center.1 + offset, center.2 - offset),
-                    _ => (i32::MAX as u32, i32::MAX as u32, i32::MAX as u32),
-                }
+                let depth = self.get_level() - 1;
+                let (x, y, z) = self.to_coords();
+                let center =
+                    |axis: u64| ((((axis as u128) << 1 | 1) << 31) >> depth).wrapping_sub(1) as u32;
+                (center(x), center(y), center(z))
+            }
+
+            fn from_coords(x: u64, y: u64, z: u64, depth: u32) -> Self {
+                debug_assert!(depth <= Self::MAX_LEVEL, "depth {} is too deep", depth);
+                debug_assert!(
+                    (x | y | z) >> depth == 0,
+                    "({}, {}, {}) is outside of the grid of depth {}",
+                    x,
+                    y,
+                    z,
+                    depth
+                );
+                interleave(x, y, z, depth) as Self
+            }
+
+            fn to_coords(self) -> (u64, u64, u64) {
+                deinterleave(self as u128, self.get_depth())
             }
         }
     };
@@ -128,3 +264,169 @@ impl_loc_code_num!(u8);
 impl_loc_code_num!(u16);
 impl_loc_code_num!(u32);
 impl_loc_code_num!(u64);
+impl_loc_code_num!(u128);
+
+/// Spread the low 21 bits of `value` to every third bit.
+fn split_by_3(value: u64) -> u64 {
+    let mut x = value & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+/// Gather every third bit of `value` into the low 21 bits.
+fn compact_by_3(value: u64) -> u64 {
+    let mut x = value & 0x1249_2492_4924_9249;
+    x = (x ^ (x >> 2)) & 0x10c3_0c30_c30c_30c3;
+    x = (x ^ (x >> 4)) & 0x100f_00f0_0f00_f00f;
+    x = (x ^ (x >> 8)) & 0x001f_0000_ff00_00ff;
+    x = (x ^ (x >> 16)) & 0x001f_0000_0000_ffff;
+    x = (x ^ (x >> 32)) & 0x1f_ffff;
+    x
+}
+
+/// Spread the low 42 bits of `value` to every third bit.
+fn spread(value: u64) -> u128 {
+    split_by_3(value) as u128 | (split_by_3(value >> 21) as u128) << 63
+}
+
+/// Gather every third bit of `value` into the low 42 bits.
+fn gather(value: u128) -> u64 {
+    compact_by_3(value as u64 & ((1 << 63) - 1)) | compact_by_3((value >> 63) as u64) << 21
+}
+
+/// Interleave coordinates into a raw LocCode of the given depth, at most 42,
+/// dropping the bits of the coordinates above it.
+///
+/// Each level stores the x, y and z bits from the highest to the lowest,
+/// which is the index of the octant in `Orientation`.
+fn interleave(x: u64, y: u64, z: u64, depth: u32) -> u128 {
+    let levels = spread((1 << depth) - 1);
+    let (x, y, z) = (spread(x) & levels, spread(y) & levels, spread(z) & levels);
+    1 << (3 * depth) | x << 2 | y << 1 | z
+}
+
+/// Split a raw LocCode of the given depth back into coordinates.
+fn deinterleave(raw: u128, depth: u32) -> (u64, u64, u64) {
+    let levels = spread((1 << depth) - 1);
+    (
+        gather((raw >> 2) & levels),
+        gather((raw >> 1) & levels),
+        gather(raw & levels),
+    )
+}
+
+/// Axis bits `(x, y, z)` of the last octant of a LocCode.
+pub(crate) fn octant_axes<L: LocCode>(loc_code: L) -> (u64, u64, u64) {
+    let (x, y, z) = loc_code.to_coords();
+    (x & 1, y & 1, z & 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coords_round_trip() {
+        for &(x, y, z, depth) in
+            [(0, 0, 0, 0), (1, 0, 1, 1), (5, 2, 7, 3), (1_000, 3, 77, 10)].iter()
+        {
+            let code = u32::from_coords(x, y, z, depth);
+            assert_eq!(code.get_level(), depth + 1);
+            assert_eq!(code.to_coords(), (x, y, z));
+        }
+        let deep = (1 << 42) - 1;
+        let code = u128::from_coords(deep, 0, deep >> 1, 42);
+        assert_eq!(code.to_coords(), (deep, 0, deep >> 1));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "outside of the grid")]
+    fn coords_out_of_grid() {
+        u32::from_coords(4, 0, 0, 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "too deep")]
+    fn coords_too_deep() {
+        u16::from_coords(0, 0, 0, 6);
+    }
+
+    #[test]
+    fn coords_match_orientation() {
+        for octant in Orientation::octants() {
+            let code = u8::root() << 3 | octant;
+            let center = octant.make_new_center(code, (0.5, 0.5, 0.5));
+            let (x, y, z) = code.to_coords();
+            assert_eq!(
+                (
+                    x as f64 * 0.5 + 0.25,
+                    y as f64 * 0.5 + 0.25,
+                    z as f64 * 0.5 + 0.25
+                ),
+                center
+            );
+        }
+    }
+
+    #[test]
+    fn hierarchy() {
+        let code = u32::from_coords(5, 2, 7, 3);
+        assert_eq!(code.parent(), Some(code >> 3));
+        assert_eq!(u32::root().parent(), None);
+        assert_eq!(code.children().count(), 8);
+        assert!(code.children().all(|child| child.parent() == Some(code)));
+        assert_eq!(code.siblings().count(), 7);
+        assert!(code.siblings().all(|sibling| sibling != code));
+        assert_eq!(u32::root().siblings().count(), 0);
+        assert_eq!(code.ancestor_at(0), Some(u32::root()));
+        assert_eq!(code.ancestor_at(3), Some(code));
+        assert_eq!(code.ancestor_at(4), None);
+        assert!(u32::root().is_ancestor_of(code));
+        assert!(!code.is_ancestor_of(code));
+        let other = u32::from_coords(4, 3, 6, 3);
+        assert_eq!(u32::common_ancestor(code, other), code >> 3);
+        assert_eq!(u32::common_ancestor(code, code >> 6), code >> 6);
+        assert_eq!(u32::common_ancestor(code, u32::from_coords(0, 0, 0, 2)), 1);
+        assert_eq!(
+            u32::from_coords(1, 1, 1, 1).octant(),
+            Some(Orientation::RFU)
+        );
+        assert_eq!(u32::root().octant(), None);
+        assert_eq!(
+            code.neighbor(Direction::L),
+            Some(u32::from_coords(4, 2, 7, 3))
+        );
+        assert_eq!(code.neighbor(Direction::UF), None);
+    }
+
+    #[test]
+    fn max_level() {
+        assert_eq!(u8::MAX_LEVEL, 2);
+        assert_eq!(u16::MAX_LEVEL, 5);
+        assert_eq!(u32::MAX_LEVEL, 10);
+        assert_eq!(u64::MAX_LEVEL, 21);
+        assert_eq!(u128::MAX_LEVEL, 42);
+        let deepest = u16::from_coords(31, 31, 31, 5);
+        assert!(deepest.checked_child(Orientation::RFU).is_none());
+        assert!((deepest >> 3).checked_child(Orientation::RFU).is_some());
+    }
+
+    #[test]
+    fn center_u32() {
+        assert_eq!(
+            u64::root().get_center_u32(),
+            (i32::MAX as u32, i32::MAX as u32, i32::MAX as u32)
+        );
+        let code = u64::from_coords(3, 0, 1, 2);
+        assert_eq!(
+            code.get_center_u32(),
+            ((7 << 29) - 1, (1 << 29) - 1, (3 << 29) - 1)
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//
+// mapped.rs in generic_octree
+// Description:
+// Memory-mapped read-only octree files, queried without deserializing.
+//
+
+use memmap2::Mmap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::path::Path;
+
+use crate::linear_storage::morton_key;
+use crate::{LinearStorage, LocCode, Morton, Octree, OctreeError, OctreeNode, OctreeStorage};
+
+/// Types that can be copied to and from raw bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding bytes and be valid for any bit
+/// pattern, such as integers, floats and `#[repr(C)]` structs of them
+/// without padding.
+pub unsafe trait Pod: Copy + Send + Sync + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+const MAGIC: [u8; 4] = *b"OCTM";
+const VERSION: u32 = 1;
+/// Magic, version, code size, payload size, max_depth and node count,
+/// padded so the codes start aligned.
+const HEADER_SIZE: usize = 32;
+
+fn bytes_of<T: Pod>(value: &T) -> &[u8] {
+    // Safe as `Pod` types have no padding.
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn read<T: Pod>(bytes: &[u8], offset: usize) -> T {
+    let bytes = &bytes[offset..offset + size_of::<T>()];
+    // Safe as `Pod` types are valid for any bit pattern.
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+fn invalid(message: &str) -> OctreeError {
+    OctreeError::Decode(message.to_string())
+}
+
+/// Read-only tree backed by a memory-mapped file.
+///
+/// The file holds a header, the codes in Morton order then the payloads,
+/// all in native byte order, so queries only touch the pages they need.
+pub struct MappedOctree<L, D> {
+    map: Mmap,
+    len: usize,
+    max_depth: u32,
+    marker: PhantomData<(L, D)>,
+}
+
+impl<L, D> MappedOctree<L, D>
+where
+    L: LocCode + Pod,
+    D: Pod,
+{
+    /// Write a tree in the memory-mappable layout.
+    pub fn save<S, P>(tree: &Octree<L, D, S>, path: P) -> Result<(), OctreeError>
+    where
+        S: OctreeStorage<L, D>,
+        P: AsRef<Path>,
+    {
+        let nodes = LinearStorage::from_unsorted(
+            tree.iter()
+                .map(|(code, node)| (code, OctreeNode::new(node.data))),
+        );
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_ne_bytes())?;
+        writer.write_all(&(size_of::<L>() as u32).to_ne_bytes())?;
+        writer.write_all(&(size_of::<D>() as u32).to_ne_bytes())?;
+        writer.write_all(&tree.max_depth().to_ne_bytes())?;
+        writer.write_all(&(nodes.len() as u64).to_ne_bytes())?;
+        writer.write_all(&[0; HEADER_SIZE - 28])?;
+        for (code, _) in nodes.as_slice() {
+            writer.write_all(bytes_of(code))?;
+        }
+        for (_, node) in nodes.as_slice() {
+            writer.write_all(bytes_of(&node.data))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Map a file written by `save`, checking that it holds codes and
+    /// payloads of the expected sizes and that every code is valid, which
+    /// reads all the codes once.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, OctreeError> {
+        let file = File::open(path)?;
+        // The file is never written through the map, it must not be
+        // modified by others while mapped.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE || map[..4] != MAGIC {
+            return Err(invalid("not a mapped octree file"));
+        }
+        if read::<u32>(&map, 4) != VERSION {
+            return Err(invalid("unsupported mapped octree version"));
+        }
+        if read::<u32>(&map, 8) as usize != size_of::<L>() {
+            return Err(invalid("LocCode size does not match the file"));
+        }
+        if read::<u32>(&map, 12) as usize != size_of::<D>() {
+            return Err(invalid("payload size does not match the file"));
+        }
+        let max_depth = read::<u32>(&map, 16);
+        if max_depth > L::MAX_LEVEL {
+            return Err(invalid("max_depth is too deep for the LocCode type"));
+        }
+        let len = usize::try_from(read::<u64>(&map, 20))
+            .map_err(|_| invalid("node count does not fit in memory"))?;
+        let size = len
+            .checked_mul(size_of::<L>() + size_of::<D>())
+            .and_then(|size| size.checked_add(HEADER_SIZE));
+        if size != Some(map.len()) {
+            return Err(invalid("truncated mapped octree file"));
+        }
+        let tree = Self {
+            map,
+            len,
+            max_depth,
+            marker: PhantomData,
+        };
+        // Queries rely on well-formed codes sorted in Morton order.
+        let mut previous = None;
+        for index in 0..len {
+            let key = morton_key(Morton::decode(tree.code(index), max_depth)?.get());
+            if previous >= Some(key) {
+                return Err(invalid("codes are not in Morton order"));
+            }
+            previous = Some(key);
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    fn code(&self, index: usize) -> L {
+        read(&self.map, HEADER_SIZE + index * size_of::<L>())
+    }
+
+    fn data(&self, index: usize) -> D {
+        let offset = HEADER_SIZE + self.len * size_of::<L>() + index * size_of::<D>();
+        read(&self.map, offset)
+    }
+
+    /// Number of nodes before the first one for which `after` holds.
+    fn partition_point<F: Fn(L) -> bool>(&self, after: F) -> usize {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = (low + high) / 2;
+            if after(self.code(middle)) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        low
+    }
+
+    /// Iterate over every node in Morton order.
+    pub fn iter(&self) -> impl Iterator<Item = (L, D)> + '_ {
+        (0..self.len).map(move |index| (self.code(index), self.data(index)))
+    }
+
+    /// Data of the node at exactly this code, as `Octree::lookup`.
+    pub fn lookup(&self, loc_code: Morton<L>) -> Option<D> {
+        let key = morton_key(loc_code.get());
+        let index = self.partition_point(|code| morton_key(code) >= key);
+        if index < self.len && self.code(index) == loc_code.get() {
+            Some(self.data(index))
+        } else {
+            None
+        }
+    }
+
+    /// Leaf containing the voxel at `(x, y, z)` of the `2^max_depth` grid,
+    /// as `Octree::leaf_at`.
+    pub fn leaf_at(&self, voxel: (u64, u64, u64)) -> Option<(Morton<L>, D)> {
+        let side = 1_u64 << self.max_depth;
+        if voxel.0 >= side || voxel.1 >= side || voxel.2 >= side {
+            return None;
+        }
+        let target = L::from_coords(voxel.0, voxel.1, voxel.2, self.max_depth);
+        let key = morton_key(target);
+        // The leaf containing the voxel is the last node up to it in Morton
+        // order, if it is one of its ancestors.
+        let index = self
+            .partition_point(|code| morton_key(code) > key)
+            .checked_sub(1)?;
+        let code = self.code(index);
+        if code == target || code.is_ancestor_of(target) {
+            // Codes were checked by `open`.
+            Some((Morton::new_unchecked(code), self.data(index)))
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the nodes of the subtree of `root`, itself included,
+    /// found in O(log n).
+    pub fn range(&self, root: Morton<L>) -> impl Iterator<Item = (L, D)> + '_ {
+        let (start, depth) = morton_key(root.get());
+        let end = start | ((1_u128 << (3 * (L::MAX_LEVEL - depth))) - 1);
+        let first = self.partition_point(|code| morton_key(code) >= (start, depth));
+        let last = self.partition_point(|code| morton_key(code) > (end, L::MAX_LEVEL));
+        (first..last).map(move |index| (self.code(index), self.data(index)))
+    }
+
+    /// Load every node into a regular tree.
+    pub fn to_octree(&self) -> Octree<L, D>
+    where
+        D: PartialEq,
+    {
+        let mut tree: Octree<L, D> = Octree::with_capacity(self.max_depth, self.len);
+        for (code, data) in self.iter() {
+            tree.content.insert(code, OctreeNode::new(data));
+        }
+        tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    #[test]
+    fn round_trip() {
+        let mut tree: Octree<u32, [u16; 2]> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), [1, 2])
+            .unwrap();
+        tree.merge(AABB::new(0.75, 0.5, 0.125, 1.0, 0.625, 0.25), [3, 4])
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("mapped-{}.octm", std::process::id()));
+        MappedOctree::save(&tree, &path).unwrap();
+        assert!(MappedOctree::<u64, [u16; 2]>::open(&path).is_err());
+        assert!(MappedOctree::<u32, u8>::open(&path).is_err());
+        let mapped = MappedOctree::<u32, [u16; 2]>::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mapped.len(), tree.size());
+        for (code, node) in tree.iter() {
+            assert_eq!(mapped.lookup(Morton::new(code).unwrap()), Some(node.data));
+        }
+        assert_eq!(mapped.lookup(Morton::root()), None);
+        let (code, data) = mapped.leaf_at((2, 3, 1)).unwrap();
+        assert_eq!((code.get(), data), (u32::from_coords(0, 0, 0, 1), [1, 2]));
+        assert_eq!(
+            mapped.leaf_at((6, 4, 1)).map(|(_, data)| data),
+            Some([3, 4])
+        );
+        assert_eq!(mapped.leaf_at((5, 4, 1)), None);
+        let upper = Morton::new(u32::from_coords(1, 1, 0, 1)).unwrap();
+        assert_eq!(mapped.range(upper).count(), 2);
+        assert_eq!(mapped.range(Morton::root()).count(), tree.size());
+        assert_eq!(mapped.to_octree().size(), tree.size());
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut tree: Octree<u32, u8> = Octree::new(2);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.5, 0.5, 0.5, 0.75, 0.75, 0.75), 2)
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("corrupt-{}.octm", std::process::id()));
+        MappedOctree::save(&tree, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let mut patches: Vec<(usize, Vec<u8>)> = vec![
+            (20, u64::MAX.to_ne_bytes().to_vec()),
+            (20, (u64::MAX / 4).to_ne_bytes().to_vec()),
+            (HEADER_SIZE, 0_u32.to_ne_bytes().to_vec()),
+            (HEADER_SIZE, 0b11_u32.to_ne_bytes().to_vec()),
+        ];
+        // Swap the two codes out of Morton order.
+        let mut swapped = bytes[HEADER_SIZE + 4..HEADER_SIZE + 8].to_vec();
+        swapped.extend_from_slice(&bytes[HEADER_SIZE..HEADER_SIZE + 4]);
+        patches.push((HEADER_SIZE, swapped));
+        for (offset, patch) in patches {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + patch.len()].copy_from_slice(&patch);
+            std::fs::write(&path, corrupt).unwrap();
+            let opened = MappedOctree::<u32, u8>::open(&path);
+            assert!(matches!(opened, Err(OctreeError::Decode(_))));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
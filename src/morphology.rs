@@ -0,0 +1,299 @@
+//
+// morphology.rs in generic_octree
+// Description:
+// Dilation, erosion, opening and closing of an Octree.
+//
+
+use crate::fill::Cells;
+use crate::octree::FITS_MAX_DEPTH;
+use crate::{LocCode, Octree, OctreeStorage, VoxelBox};
+
+/// Shape swept around every voxel by morphological operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuringElement {
+    /// Cube of the given radius in voxels, edge being `2 * radius + 1`.
+    Cube(u32),
+    /// Ball of the given radius in voxels.
+    Sphere(u32),
+}
+
+/// Data given to the voxels added by a dilation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Propagation<D> {
+    /// Data of the closest voxel of the original tree.
+    Nearest,
+    /// Greatest data among the voxels reaching the new one.
+    Max,
+    /// Same data for every added voxel.
+    Fixed(D),
+}
+
+/// A structuring element as a list of `(offset, grow)` boxes: sweeping it
+/// around a box is the union of the box moved by `offset` and grown by `grow`.
+type Sweep = Vec<([i64; 3], [i64; 3])>;
+
+impl StructuringElement {
+    /// Boxes making up the element.
+    fn sweep(self) -> Sweep {
+        match self {
+            Self::Cube(radius) => {
+                let radius = radius as i64;
+                vec![([0, 0, 0], [radius, radius, radius])]
+            }
+            Self::Sphere(radius) => Self::ball(radius as i64 * radius as i64),
+        }
+    }
+
+    /// Rows of voxels at a squared distance of at most `squared` from the center.
+    fn ball(squared: i64) -> Sweep {
+        let radius = isqrt(squared);
+        let mut rows = vec![];
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                let rest = squared - y * y - z * z;
+                if rest >= 0 {
+                    rows.push(([0, y, z], [isqrt(rest), 0, 0]));
+                }
+            }
+        }
+        rows
+    }
+
+    /// Elements of growing size, each adding the voxels at the next distance.
+    fn layers(self) -> Vec<Sweep> {
+        match self {
+            Self::Cube(radius) => (1..=radius)
+                .map(|layer| Self::Cube(layer).sweep())
+                .collect(),
+            Self::Sphere(radius) => {
+                let radius = radius as i64;
+                let mut distances = vec![];
+                for x in 0..=radius {
+                    for y in 0..=radius {
+                        for z in 0..=radius {
+                            let squared = x * x + y * y + z * z;
+                            if squared != 0 && squared <= radius * radius {
+                                distances.push(squared);
+                            }
+                        }
+                    }
+                }
+                distances.sort();
+                distances.dedup();
+                distances.into_iter().map(Self::ball).collect()
+            }
+        }
+    }
+}
+
+fn isqrt(value: i64) -> i64 {
+    let mut root = (value as f64).sqrt() as i64;
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= value {
+        root += 1;
+    }
+    root
+}
+
+fn swept(boxes: &[VoxelBox], sweep: &[([i64; 3], [i64; 3])]) -> Vec<VoxelBox> {
+    boxes
+        .iter()
+        .flat_map(|voxel_box| {
+            sweep
+                .iter()
+                .map(move |(offset, grow)| voxel_box.shift_grow(*offset, *grow))
+        })
+        .collect()
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D> + Clone,
+{
+    fn leaf_boxes(&self) -> Vec<(VoxelBox, D)> {
+        self.content
+            .iter()
+            .map(|(code, node)| {
+                let voxel_box =
+                    VoxelBox::from_loc_code(code, self.max_depth()).expect(FITS_MAX_DEPTH);
+                (voxel_box, node.data)
+            })
+            .collect()
+    }
+
+    /// Dilate `leaves`, which are sorted by data for `Propagation::Max` so
+    /// the greatest data is painted last.
+    fn dilate_leaves(
+        &self,
+        element: StructuringElement,
+        propagation: Propagation<D>,
+        leaves: Vec<(VoxelBox, D)>,
+    ) -> Self {
+        let mut boxes = vec![];
+        match propagation {
+            Propagation::Nearest => {
+                for sweep in element.layers().into_iter().rev() {
+                    for (voxel_box, data) in leaves.iter() {
+                        boxes.extend(
+                            swept(&[*voxel_box], &sweep)
+                                .into_iter()
+                                .map(|voxel_box| (voxel_box, Some(*data))),
+                        );
+                    }
+                }
+            }
+            Propagation::Max => {
+                let sweep = element.sweep();
+                for (voxel_box, data) in leaves.iter() {
+                    boxes.extend(
+                        swept(&[*voxel_box], &sweep)
+                            .into_iter()
+                            .map(|voxel_box| (voxel_box, Some(*data))),
+                    );
+                }
+            }
+            Propagation::Fixed(value) => {
+                let sweep = element.sweep();
+                for (voxel_box, _) in leaves.iter() {
+                    boxes.extend(
+                        swept(&[*voxel_box], &sweep)
+                            .into_iter()
+                            .map(|voxel_box| (voxel_box, Some(value))),
+                    );
+                }
+            }
+        }
+        boxes.extend(
+            leaves
+                .into_iter()
+                .map(|(voxel_box, data)| (voxel_box, Some(data))),
+        );
+
+        let mut tree = Self::new(self.max_depth());
+        tree.set_world_transform(self.world_transform);
+        tree.paint(boxes);
+        tree
+    }
+
+    /// Shrink the tree by the structuring element, removing every voxel the
+    /// element centered on it would not entirely fit in the tree.
+    /// Space outside of the root cube is considered empty.
+    pub fn erode(&self, element: StructuringElement) -> Self {
+        let max_depth = self.max_depth();
+        let side = 1_i64 << max_depth;
+        let mut empty = Cells::new(self)
+            .empty_cells()
+            .into_iter()
+            .map(|code| VoxelBox::from_loc_code(code, max_depth).expect(FITS_MAX_DEPTH))
+            .collect::<Vec<VoxelBox>>();
+        for axis in 0..3 {
+            let (mut below, mut above) = ([side + 1; 3], [-1; 3]);
+            below[axis] = 0;
+            above[axis] = side;
+            empty.push(VoxelBox::from_corners([-1; 3], below));
+            empty.push(VoxelBox::from_corners(above, [side + 1; 3]));
+        }
+
+        let mut tree = self.clone();
+        tree.paint(
+            swept(&empty, &element.sweep())
+                .into_iter()
+                .map(|voxel_box| (voxel_box, None))
+                .collect(),
+        );
+        tree
+    }
+
+    /// Erode then dilate back, removing details smaller than the element.
+    pub fn open(&self, element: StructuringElement) -> Self {
+        let eroded = self.erode(element);
+        eroded.dilate_leaves(element, Propagation::Nearest, eroded.leaf_boxes())
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + PartialOrd + Send + Sync,
+    S: OctreeStorage<T, D> + Clone,
+{
+    /// Grow the tree by the structuring element, original voxels keeping
+    /// their data and new ones getting it according to `propagation`.
+    pub fn dilate(&self, element: StructuringElement, propagation: Propagation<D>) -> Self {
+        let mut leaves = self.leaf_boxes();
+        if let Propagation::Max = propagation {
+            leaves.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        self.dilate_leaves(element, propagation, leaves)
+    }
+
+    /// Dilate then erode back, filling holes and gaps smaller than the element.
+    pub fn close(&self, element: StructuringElement, propagation: Propagation<D>) -> Self {
+        self.dilate(element, propagation).erode(element)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    fn voxel(tree: &Octree<u32, u8>, x: u64, y: u64, z: u64) -> Option<u8> {
+        tree.leaf_at((x, y, z)).map(|(_, node)| node.data)
+    }
+
+    #[test]
+    fn dilate_cube() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.25, 0.25, 0.25, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        let dilated = tree.dilate(StructuringElement::Cube(1), Propagation::Fixed(2));
+        assert_eq!(voxel(&dilated, 2, 2, 2), Some(1));
+        assert_eq!(voxel(&dilated, 1, 1, 1), Some(2));
+        assert_eq!(voxel(&dilated, 4, 4, 4), Some(2));
+        assert_eq!(voxel(&dilated, 5, 4, 4), None);
+    }
+
+    #[test]
+    fn dilate_sphere() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.375, 0.375, 0.375, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        let dilated = tree.dilate(StructuringElement::Sphere(1), Propagation::Nearest);
+        assert_eq!(voxel(&dilated, 2, 3, 3), Some(1));
+        assert_eq!(voxel(&dilated, 2, 2, 3), None);
+        assert_eq!(dilated.size(), 7);
+    }
+
+    #[test]
+    fn open_removes_noise() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.75, 0.75, 0.75, 0.875, 0.875, 0.875), 1)
+            .unwrap();
+        let eroded = tree.erode(StructuringElement::Cube(1));
+        assert_eq!(voxel(&eroded, 0, 0, 0), None);
+        assert_eq!(voxel(&eroded, 2, 2, 2), Some(1));
+        let opened = tree.open(StructuringElement::Cube(1));
+        assert_eq!(voxel(&opened, 6, 6, 6), None);
+        assert_eq!(voxel(&opened, 0, 0, 0), Some(1));
+        assert_eq!(opened.size(), 1);
+    }
+
+    #[test]
+    fn unordered_data() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Material(u8);
+
+        let mut tree: Octree<u32, Material> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), Material(1))
+            .unwrap();
+        assert_eq!(tree.erode(StructuringElement::Cube(1)).size(), 8);
+        assert_eq!(tree.open(StructuringElement::Cube(1)).size(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+//
+// morton.rs in generic_octree
+// Description:
+// Well-formed LocCode newtype.
+//
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{LocCode, Orientation};
+
+#[cfg(feature = "serialize")]
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A LocCode known to be well formed: its sentinel bit is set on a level
+/// boundary and no bit is set above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Morton<L: LocCode>(L);
+
+/// Error returned when a raw integer is not a well-formed LocCode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidMorton(pub u128);
+
+impl fmt::Display for InvalidMorton {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x} is not a well-formed LocCode", self.0)
+    }
+}
+
+impl std::error::Error for InvalidMorton {}
+
+/// Error returned when parsing a LocCode path such as `r/3/5/7`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseMortonError {
+    /// The path does not start with the `r` root segment.
+    MissingRoot,
+    /// A segment is neither an octant index nor an octant name.
+    InvalidOctant(String),
+    /// The path is deeper than the LocCode type can hold.
+    TooDeep { max_depth: u32 },
+}
+
+impl fmt::Display for ParseMortonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingRoot => write!(f, "LocCode path must start with `r`"),
+            Self::InvalidOctant(segment) => write!(f, "`{}` is not an octant", segment),
+            Self::TooDeep { max_depth } => {
+                write!(f, "LocCode path is deeper than {} levels", max_depth)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseMortonError {}
+
+impl<L: LocCode> Morton<L> {
+    /// Wrap a LocCode, `None` if it is not well formed.
+    pub fn new(loc_code: L) -> Option<Self> {
+        let raw: u128 = loc_code.into();
+        // The sentinel bit sits at a multiple of 3, bit 127 being one more.
+        if raw != 0 && raw.leading_zeros() % 3 == 1 {
+            Some(Self(loc_code))
+        } else {
+            None
+        }
+    }
+
+    /// Convert a LocCode stored by versions up to 0.5, which numbered the
+    /// octants differently, `None` if it is not well formed.
+    pub fn from_legacy(loc_code: L) -> Option<Self> {
+        let depth = Self::new(loc_code)?.get().get_depth();
+        let code = (1..=depth)
+            .filter_map(|level| loc_code.ancestor_at(level)?.octant())
+            .try_fold(L::root(), |code, octant| {
+                code.checked_child(Orientation::LEGACY[u8::from(octant) as usize])
+            })?;
+        Some(Self(code))
+    }
+
+    /// Wrap a LocCode read from a file, failing if it is not well formed or
+    /// deeper than `max_depth`.
+    #[cfg(any(feature = "dot_tree", feature = "mmap"))]
+    pub(crate) fn decode(loc_code: L, max_depth: u32) -> Result<Self, crate::OctreeError> {
+        match Self::new(loc_code) {
+            Some(code) if loc_code.get_depth() <= max_depth => Ok(code),
+            _ => Err(crate::OctreeError::Decode(format!(
+                "invalid node code {}",
+                InvalidMorton(loc_code.into())
+            ))),
+        }
+    }
+
+    /// Wrap a LocCode coming from the tree itself.
+    pub(crate) fn new_unchecked(loc_code: L) -> Self {
+        Self(loc_code)
+    }
+
+    /// Code of the root node.
+    pub fn root() -> Self {
+        Self(L::root())
+    }
+
+    /// Get the wrapped LocCode.
+    pub fn get(self) -> L {
+        self.0
+    }
+
+    /// Octant index of each level, from the root down.
+    pub fn path(self) -> impl Iterator<Item = u8> {
+        let raw: u128 = self.0.into();
+        (0..self.0.get_depth())
+            .rev()
+            .map(move |level| ((raw >> (3 * level)) & 7) as u8)
+    }
+}
+
+/// Formats the code as the octant index of each level from the root, such as
+/// `r/3/5/7`, or with the octant names, such as `r/LFU/RFD/RFU`, using `{:#}`.
+impl<L: LocCode> fmt::Display for Morton<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r")?;
+        for octant in self.path() {
+            if f.alternate() {
+                write!(f, "/{}", Orientation::try_from(octant).unwrap())?;
+            } else {
+                write!(f, "/{}", octant)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses paths written by `Display`, octant indexes and names can be mixed.
+impl<L: LocCode> FromStr for Morton<L> {
+    type Err = ParseMortonError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut segments = path.split('/');
+        if segments.next() != Some("r") {
+            return Err(ParseMortonError::MissingRoot);
+        }
+        segments.try_fold(Self::root(), |code, segment| {
+            let octant = match segment.parse::<u8>() {
+                Ok(index) if index < 8 => Orientation::try_from(index).unwrap(),
+                _ => Orientation::octants()
+                    .find(|octant| octant.to_string().eq_ignore_ascii_case(segment))
+                    .ok_or_else(|| ParseMortonError::InvalidOctant(segment.to_string()))?,
+            };
+            code.0
+                .checked_child(octant)
+                .map(Self)
+                .ok_or(ParseMortonError::TooDeep {
+                    max_depth: L::MAX_LEVEL,
+                })
+        })
+    }
+}
+
+macro_rules! impl_morton_num {
+    ($ty:ident) => {
+        impl TryFrom<$ty> for Morton<$ty> {
+            type Error = InvalidMorton;
+
+            fn try_from(loc_code: $ty) -> Result<Self, Self::Error> {
+                Self::new(loc_code).ok_or(InvalidMorton(loc_code as u128))
+            }
+        }
+
+        impl From<Morton<$ty>> for $ty {
+            fn from(morton: Morton<$ty>) -> $ty {
+                morton.0
+            }
+        }
+    };
+}
+
+impl_morton_num!(u8);
+impl_morton_num!(u16);
+impl_morton_num!(u32);
+impl_morton_num!(u64);
+impl_morton_num!(u128);
+
+/// Serialized as a path string in human-readable formats and as the raw
+/// integer otherwise.
+#[cfg(feature = "serialize")]
+impl<L: LocCode + Serialize> Serialize for Morton<L> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de, L: LocCode + Deserialize<'de>> Deserialize<'de> for Morton<L> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let path = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+            path.parse().map_err(D::Error::custom)
+        } else {
+            let loc_code = L::deserialize(deserializer)?;
+            Self::new(loc_code).ok_or_else(|| D::Error::custom(InvalidMorton(loc_code.into())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn well_formed() {
+        assert!(Morton::new(1_u8).is_some());
+        assert!(Morton::new(0b1_101_u16).is_some());
+        assert!(Morton::new(1_u128 << 126).is_some());
+        assert!(Morton::new(0_u32).is_none());
+        assert!(Morton::new(0b11_u32).is_none());
+        assert!(Morton::<u64>::try_from(0b10_000).is_err());
+    }
+
+    #[test]
+    fn legacy() {
+        // Old octant 3 is the lowest corner, old octant 1 the one above it
+        // and in front.
+        let code = Morton::from_legacy(0b1_011_001_u32).unwrap();
+        assert_eq!(code.get(), u32::from_coords(0, 1, 1, 2));
+        assert!(Morton::from_legacy(0_u32).is_none());
+    }
+
+    #[test]
+    fn path() {
+        let code = Morton::new(0b1_011_101_111_u32).unwrap();
+        assert_eq!(code.to_string(), "r/3/5/7");
+        assert_eq!(format!("{:#}", code), "r/LFU/RFD/RFU");
+        assert_eq!(Morton::<u32>::root().to_string(), "r");
+        assert_eq!("r/3/5/7".parse(), Ok(code));
+        assert_eq!("r/lfu/5/RFU".parse(), Ok(code));
+        assert_eq!("r".parse(), Ok(Morton::<u8>::root()));
+        assert_eq!(
+            "3/5".parse::<Morton<u8>>(),
+            Err(ParseMortonError::MissingRoot)
+        );
+        assert!("r/8".parse::<Morton<u8>>().is_err());
+        assert_eq!(
+            "r/1/2/3".parse::<Morton<u8>>(),
+            Err(ParseMortonError::TooDeep { max_depth: 2 })
+        );
+    }
+}
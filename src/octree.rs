@@ -1,7 +1,11 @@
 use hashbrown::HashMap;
 use hashbrown::HashSet;
+use std::marker::PhantomData;
 
-use crate::{LocCode, OctreeNode, Orientation, AABB};
+use crate::{
+    LocCode, Morton, OctreeError, OctreeNode, OctreeStorage, Orientation, VoxelBox, WorldTransform,
+    AABB,
+};
 use rayon::prelude::*;
 
 #[cfg(feature = "serialize")]
@@ -11,117 +15,329 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use flate2::Compression;
 
 #[cfg(feature = "serialize")]
-use flate2::write::{ZlibDecoder, ZlibEncoder};
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder};
 
 #[cfg(feature = "serialize")]
-use std::{io::prelude::*, path::Path};
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufReader, BufWriter},
+    path::Path,
+};
 
-#[derive(Debug)]
+#[cfg(feature = "dot_tree")]
+use crate::format::Header;
+
+/// Panic message of the constructors taking a `max_depth` deeper than
+/// `L::MAX_LEVEL`.
+pub(crate) const TOO_DEEP: &str = "max_depth is too deep for the LocCode type";
+/// Panic message for a node of a tree deeper than its `max_depth`, which
+/// edits never insert.
+pub(crate) const FITS_MAX_DEPTH: &str = "nodes of a tree fit in its max_depth";
+
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-pub struct Octree<L: LocCode, D: Send + Sync> {
-    pub content: HashMap<L, OctreeNode<D>>,
+pub struct Octree<L: LocCode, D: Send + Sync, S = HashMap<L, OctreeNode<D>>> {
+    pub(crate) content: S,
     max_depth: u32,
+    pub(crate) world_transform: Option<WorldTransform>,
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    marker: PhantomData<(L, D)>,
 }
 
 #[cfg(feature = "dot_tree")]
-impl<L, D> Octree<L, D>
+impl<L, D, S> Octree<L, D, S>
 where
     L: LocCode + Serialize + DeserializeOwned,
     D: Send + Sync + Serialize + DeserializeOwned,
+    S: OctreeStorage<L, D> + Serialize + DeserializeOwned,
 {
-    /// Load from voxel octree from files
-    /// TODO: Add better error management
-    pub fn load_from_file<P: AsRef<Path>>(path_ref: P) -> Result<Self, std::io::Error> {
+    /// Load a voxel octree from a `.tree` file, checking that it was
+    /// written for the same LocCode and payload sizes.
+    pub fn load_from_file<P: AsRef<Path>>(path_ref: P) -> Result<Self, OctreeError> {
         let path = path_ref.as_ref();
-        match path.extension() {
-            Some(x) => match x.to_str() {
-                #[cfg(feature = "dot_tree")]
-                Some("tree") => {
-                    let file = std::fs::File::open(path)?;
-                    let contents: Vec<u8> = file.bytes().filter_map(Result::ok).collect();
-                    let mut decoder = ZlibDecoder::new(Vec::new());
-                    decoder.write_all(&contents)?;
-                    let contents = decoder.finish()?;
-                    let tree = bincode::deserialize_from::<&[u8], Self>(&contents).unwrap();
-                    Ok(tree)
-                }
-                _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-            },
-            None => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+        match path.extension().map(|x| x.to_string_lossy()) {
+            Some(x) if x == "tree" => Self::read_from(File::open(path)?),
+            x => Err(OctreeError::UnsupportedExtension(x.map(String::from))),
         }
     }
 
     /// Save octree to file
-    pub fn save_to_file<P: AsRef<Path>>(&self, path_ref: P) -> Result<(), std::io::Error> {
-        let path = path_ref.as_ref();
-        let binary = bincode::serialize(self).unwrap();
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&binary)?;
-        std::fs::write(path, encoder.finish()?)
+    pub fn save_to_file<P: AsRef<Path>>(&self, path_ref: P) -> Result<(), OctreeError> {
+        self.write_to(BufWriter::new(File::create(path_ref)?))
+    }
+
+    /// Read a tree in the `.tree` format, decompressing it as it is read.
+    ///
+    /// `reader` is buffered here, so bytes following the tree may be read
+    /// too. Only the LocCode and payload sizes are checked, use
+    /// `read_from_tagged` to also check the payload tag.
+    pub fn read_from<R: Read>(reader: R) -> Result<Self, OctreeError> {
+        Self::read_checked(reader, None)
+    }
+
+    /// Read a tree written by `write_to_tagged` with the same payload tag.
+    pub fn read_from_tagged<R: Read>(reader: R, payload_tag: &str) -> Result<Self, OctreeError> {
+        Self::read_checked(reader, Some(payload_tag))
+    }
+
+    fn read_checked<R: Read>(reader: R, payload_tag: Option<&str>) -> Result<Self, OctreeError> {
+        let mut reader = BufReader::new(reader);
+        let header = Header::read(&mut reader)?.ok_or_else(|| {
+            OctreeError::Incompatible("no header, the file needs Octree::migrate_file".to_string())
+        })?;
+        header.check::<L, D>(payload_tag)?;
+        let mut decoder = ZlibDecoder::new(reader);
+        let tree: Self = bincode::deserialize_from(&mut decoder)?;
+        // Reach the end of the stream so that its checksum is verified.
+        let mut rest = vec![];
+        decoder
+            .read_to_end(&mut rest)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::InvalidData
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::UnexpectedEof => OctreeError::Decode(error.to_string()),
+                _ => OctreeError::Io(error),
+            })?;
+        if !rest.is_empty() {
+            return Err(OctreeError::Decode(
+                "trailing data after the tree".to_string(),
+            ));
+        }
+        if tree.max_depth != header.max_depth() {
+            return Err(OctreeError::Decode(
+                "max_depth does not match the header".to_string(),
+            ));
+        }
+        for (loc_code, _) in tree.iter() {
+            Morton::decode(loc_code, tree.max_depth)?;
+        }
+        Ok(tree)
+    }
+
+    /// Write the tree in the `.tree` format, compressing it as it is
+    /// written.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<(), OctreeError> {
+        self.write_to_tagged(writer, "")
+    }
+
+    /// Write the tree with a payload tag, a stable name of the payload type
+    /// picked by the caller and checked by `read_from_tagged`.
+    pub fn write_to_tagged<W: Write>(
+        &self,
+        mut writer: W,
+        payload_tag: &str,
+    ) -> Result<(), OctreeError> {
+        writer.write_all(&Header::new::<L, D>(self.max_depth, payload_tag).to_bytes())?;
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        bincode::serialize_into(&mut encoder, self)?;
+        encoder.finish()?.flush()?;
+        Ok(())
     }
 }
 
-impl<'a, L, D> Octree<L, D>
+impl<L, D, S> Octree<L, D, S>
 where
     L: LocCode,
     D: Send + Sync,
+    S: OctreeStorage<L, D>,
 {
     /// Get the size of an octree
     pub fn size(&self) -> usize {
         self.content.len()
     }
+
+    /// Get the maximum depth nodes can be subdivided to.
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Storage holding the nodes of the tree.
+    pub fn storage(&self) -> &S {
+        &self.content
+    }
+
+    /// Iterate over the nodes of the tree, in no particular order.
+    pub fn iter(&self) -> S::Iter<'_> {
+        self.content.iter()
+    }
 }
 
-impl<'a, T, D> Octree<T, D>
+impl<T, D, S> Octree<T, D, S>
 where
     T: LocCode,
     D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
 {
     /// Create a new Octree
+    ///
+    /// Panics if `max_depth` is deeper than `T::MAX_LEVEL`, see `try_new`.
     pub fn new(max_depth: u32) -> Self {
-        let content = HashMap::default();
-        Self { content, max_depth }
+        Self::try_new(max_depth).expect(TOO_DEEP)
+    }
+
+    /// Create a new Octree, failing if `max_depth` is deeper than
+    /// `T::MAX_LEVEL`.
+    pub fn try_new(max_depth: u32) -> Result<Self, OctreeError> {
+        Self::try_with_capacity(max_depth, 0)
     }
 
     /// Create an Octree with given pre-allocated space.
+    ///
+    /// Panics if `max_depth` is deeper than `T::MAX_LEVEL`, see
+    /// `try_with_capacity`.
     pub fn with_capacity(max_depth: u32, size: usize) -> Self {
-        let content = HashMap::with_capacity(size);
-        Self { content, max_depth }
+        Self::try_with_capacity(max_depth, size).expect(TOO_DEEP)
+    }
+
+    /// Create an Octree with given pre-allocated space, failing if
+    /// `max_depth` is deeper than `T::MAX_LEVEL`.
+    pub fn try_with_capacity(max_depth: u32, size: usize) -> Result<Self, OctreeError> {
+        Self::try_with_storage(max_depth, S::with_capacity(size))
+    }
+
+    /// Create an Octree around existing nodes, failing if `max_depth` is
+    /// deeper than `T::MAX_LEVEL`.
+    pub fn try_with_storage(max_depth: u32, content: S) -> Result<Self, OctreeError> {
+        if max_depth > T::MAX_LEVEL {
+            return Err(OctreeError::DepthOverflow {
+                depth: max_depth,
+                max_depth: T::MAX_LEVEL,
+            });
+        }
+        Ok(Self {
+            content,
+            max_depth,
+            world_transform: None,
+            marker: PhantomData,
+        })
+    }
+
+    /// Copy the tree into another storage backend.
+    pub fn convert_storage<U: OctreeStorage<T, D>>(&self) -> Octree<T, D, U> {
+        let mut content = U::with_capacity(self.content.len());
+        for (loc_code, node) in self.content.iter() {
+            content.insert(loc_code, OctreeNode::new(node.data));
+        }
+        Octree {
+            content,
+            max_depth: self.max_depth,
+            world_transform: self.world_transform,
+            marker: PhantomData,
+        }
     }
 
     pub fn depth(&self) -> u32 {
-        let keys = self.content.keys();
-        keys.max().unwrap_or(&T::root()).get_level()
+        let keys = self.content.iter().map(|(loc_code, _)| loc_code);
+        keys.max().unwrap_or_else(T::root).get_level()
+    }
+
+    /// Get every node that has descendants in the tree.
+    pub(crate) fn ancestors(&self) -> HashSet<T> {
+        let mut ancestors = HashSet::new();
+        for (code, _) in self.content.iter() {
+            let mut parent = code.parent();
+            while let Some(code) = parent.filter(|code| ancestors.insert(*code)) {
+                parent = code.parent();
+            }
+        }
+        ancestors
     }
 
     /// Return a tree node a node.
-    pub fn lookup(&self, loc_code: T) -> Option<&OctreeNode<D>> {
-        self.content.get(&loc_code)
+    pub fn lookup(&self, loc_code: Morton<T>) -> Option<&OctreeNode<D>> {
+        self.content.get(&loc_code.get())
     }
 
-    /// Insert a tree node.
-    pub fn insert(&mut self, location: T, node: OctreeNode<D>) -> T {
-        self.content.insert(location, node);
-        location
+    /// Return the leaf containing the voxel at `(x, y, z)` of the
+    /// `2^max_depth` grid, `None` if the voxel is empty or out of the tree.
+    pub fn leaf_at(&self, voxel: (u64, u64, u64)) -> Option<(Morton<T>, &OctreeNode<D>)> {
+        let side = 1_u64 << self.max_depth;
+        if voxel.0 >= side || voxel.1 >= side || voxel.2 >= side {
+            return None;
+        }
+        let code = T::from_coords(voxel.0, voxel.1, voxel.2, self.max_depth);
+        (0..=self.max_depth).rev().find_map(|depth| {
+            let ancestor = code.ancestor_at(depth)?;
+            self.content
+                .get(&ancestor)
+                .map(|node| (Morton::new_unchecked(ancestor), node))
+        })
     }
 
-    pub fn remove_node(&mut self, loc_code: T) {
-        self.content.remove(&loc_code);
+    /// Insert a tree node, failing if it is deeper than `max_depth`.
+    pub fn insert(
+        &mut self,
+        location: Morton<T>,
+        node: OctreeNode<D>,
+    ) -> Result<Morton<T>, OctreeError> {
+        let depth = location.get().get_depth();
+        if depth > self.max_depth {
+            return Err(OctreeError::DepthOverflow {
+                depth,
+                max_depth: self.max_depth,
+            });
+        }
+        self.content.insert(location.get(), node);
+        Ok(location)
+    }
+
+    pub fn remove_node(&mut self, loc_code: Morton<T>) {
+        self.content.remove(&loc_code.get());
     }
 
     /// Merge an AABB into the tree
-    pub fn merge(&mut self, aabb: AABB, data: D) {
-        let mut codes: Vec<T> = self
-            .merge_inner(aabb, data, (0.5, 0.5, 0.5), 1, T::root())
+    ///
+    /// Fails without changing the tree if `max_depth` is deeper than
+    /// `T::MAX_LEVEL`, which can only happen for deserialized trees.
+    pub fn merge(&mut self, aabb: AABB, data: D) -> Result<(), OctreeError> {
+        if self.max_depth > T::MAX_LEVEL {
+            return Err(OctreeError::DepthOverflow {
+                depth: self.max_depth,
+                max_depth: T::MAX_LEVEL,
+            });
+        }
+        let codes: Vec<T> = self
+            .merge_inner(aabb, data, (0.5, 0.5, 0.5), 1, T::root())?
+            .into_iter()
+            .collect();
+        self.collapse(codes);
+        Ok(())
+    }
+
+    /// Merge a VoxelBox into the tree, without any floating point rounding.
+    pub fn merge_box(&mut self, voxel_box: VoxelBox, data: D) -> Result<(), OctreeError> {
+        if self.max_depth > T::MAX_LEVEL {
+            return Err(OctreeError::DepthOverflow {
+                depth: self.max_depth,
+                max_depth: T::MAX_LEVEL,
+            });
+        }
+        let voxel_box = match voxel_box.clip(self.max_depth) {
+            Some(voxel_box) => voxel_box.with_orientation(Orientation::default()),
+            None => return Ok(()),
+        };
+        if self.max_depth == 0 {
+            self.content.insert(T::root(), OctreeNode::new(data));
+            return Ok(());
+        }
+        let half = 1_i64 << (self.max_depth - 1);
+        let codes: Vec<T> = self
+            .merge_box_inner(voxel_box, data, (half, half, half), 1, T::root())?
             .into_iter()
             .collect();
+        self.collapse(codes);
+        Ok(())
+    }
+
+    /// Collapse the children of the given nodes into their parent when they
+    /// all hold the same data, going up the tree until nothing changes.
+    pub(crate) fn collapse(&mut self, mut codes: Vec<T>) {
         while !codes.is_empty() {
             codes.sort();
             codes.reverse();
             codes = codes
                 .into_iter()
                 .filter_map(|code| self.assemble(code))
-                .filter(|code| *code > T::zero())
                 .collect::<HashSet<T>>()
                 .into_iter()
                 .collect();
@@ -129,9 +345,9 @@ where
     }
 
     fn assemble(&mut self, code: T) -> Option<T> {
-        let datas = (0_u8..8_u8)
-            .map(|number| (code << T::three()) | T::from(number))
-            .filter_map(|loc_code| self.lookup(loc_code))
+        let datas = code
+            .children()
+            .filter_map(|loc_code| self.content.get(&loc_code))
             .map(|node| node.data)
             .collect::<Vec<D>>();
         if datas.len() != 8 {
@@ -145,15 +361,176 @@ where
             if !is_same {
                 None
             } else {
-                (0_u8..8_u8)
-                    .map(|number| (code << T::three()) | T::from(number))
-                    .for_each(|code| self.remove_node(code));
-                self.insert(code, OctreeNode::new(elem));
-                Some(code >> T::three())
+                code.children().for_each(|code| {
+                    self.content.remove(&code);
+                });
+                self.content.insert(code, OctreeNode::new(elem));
+                code.parent()
             }
         }
     }
 
+    /// Internal function for recursively merging AABB.
+    /// Returns a HashSet containing all the node that are affected by the merging, not all new nodes
+    /// These affected nodes can be scheduled to merge data outside here
+    fn merge_inner(
+        &mut self,
+        aabb: AABB,
+        data: D,
+        center: (f64, f64, f64),
+        depth: u32,
+        loc_code: T,
+    ) -> Result<HashSet<T>, OctreeError> {
+        let blocks = aabb.explode(center);
+        let max_depth = self.max_depth;
+
+        let (fitting, subdivisables): (Vec<AABB>, Vec<AABB>) = blocks
+            .into_iter()
+            .partition(|aabb| aabb.fit_in(depth, max_depth));
+
+        let mut codes = HashSet::new();
+        for elem in fitting {
+            let code = Self::child_code(loc_code, elem.orientation)?;
+            self.content.insert(code, OctreeNode::new(data));
+            codes.insert(loc_code);
+        }
+
+        if depth != self.max_depth {
+            for aabb in subdivisables {
+                let new_loc_code = Self::child_code(loc_code, aabb.orientation)?;
+                let new_center = aabb.orientation.make_new_center(new_loc_code, center);
+                codes.extend(self.merge_inner(
+                    aabb.with_orientation(Orientation::default()),
+                    data,
+                    new_center,
+                    depth + 1,
+                    new_loc_code,
+                )?);
+            }
+        }
+
+        Ok(codes)
+    }
+
+    /// Internal function for recursively merging VoxelBox, as `merge_inner`.
+    fn merge_box_inner(
+        &mut self,
+        voxel_box: VoxelBox,
+        data: D,
+        center: (i64, i64, i64),
+        depth: u32,
+        loc_code: T,
+    ) -> Result<HashSet<T>, OctreeError> {
+        let max_depth = self.max_depth;
+        let (fitting, subdivisables): (Vec<VoxelBox>, Vec<VoxelBox>) = voxel_box
+            .explode(center)
+            .into_iter()
+            .partition(|part| part.fit_in(depth, max_depth));
+
+        let mut codes = HashSet::new();
+        for part in fitting {
+            let code = Self::child_code(loc_code, part.orientation)?;
+            self.content.insert(code, OctreeNode::new(data));
+            codes.insert(loc_code);
+        }
+
+        if depth != max_depth {
+            for part in subdivisables {
+                let new_loc_code = Self::child_code(loc_code, part.orientation)?;
+                let new_center = VoxelBox::child_center(new_loc_code, center, max_depth);
+                codes.extend(self.merge_box_inner(
+                    part.with_orientation(Orientation::default()),
+                    data,
+                    new_center,
+                    depth + 1,
+                    new_loc_code,
+                )?);
+            }
+        }
+
+        Ok(codes)
+    }
+
+    fn child_code(loc_code: T, orientation: Orientation) -> Result<T, OctreeError> {
+        loc_code
+            .checked_child(orientation)
+            .ok_or(OctreeError::DepthOverflow {
+                depth: loc_code.get_level(),
+                max_depth: T::MAX_LEVEL,
+            })
+    }
+
+    /// Set every voxel of each box to its data, `None` erasing them.
+    /// Boxes are applied in order, so later ones overwrite earlier ones.
+    pub(crate) fn paint(&mut self, boxes: Vec<(VoxelBox, Option<D>)>) {
+        let mut internal = self.ancestors();
+        let mut touched = HashSet::new();
+        for (voxel_box, data) in boxes {
+            if let Some(voxel_box) = voxel_box.clip(self.max_depth) {
+                self.paint_inner(&voxel_box, data, T::root(), &mut internal, &mut touched);
+            }
+        }
+        self.collapse(touched.into_iter().collect());
+    }
+
+    /// Internal function for recursively painting a box.
+    /// `internal` holds every node that may have descendants, `touched` gets
+    /// the parents of the new leaves so they can be collapsed afterwards.
+    fn paint_inner(
+        &mut self,
+        voxel_box: &VoxelBox,
+        data: Option<D>,
+        loc_code: T,
+        internal: &mut HashSet<T>,
+        touched: &mut HashSet<T>,
+    ) {
+        let node =
+            VoxelBox::from_loc_code(loc_code, self.max_depth).expect("painting stops at max_depth");
+        if !voxel_box.intersects(&node) {
+            return;
+        }
+        if voxel_box.contains(&node) {
+            self.clear_inner(loc_code, internal);
+            if let Some(data) = data {
+                self.content.insert(loc_code, OctreeNode::new(data));
+                if let Some(parent) = loc_code.parent() {
+                    touched.insert(parent);
+                }
+            }
+            return;
+        }
+        if let Some(node) = self.content.remove(&loc_code) {
+            loc_code.children().for_each(|code| {
+                self.content.insert(code, OctreeNode::new(node.data));
+            });
+            internal.insert(loc_code);
+        } else if !internal.contains(&loc_code) {
+            if data.is_none() {
+                return;
+            }
+            internal.insert(loc_code);
+        }
+        for code in loc_code.children() {
+            self.paint_inner(voxel_box, data, code, internal, touched);
+        }
+    }
+
+    /// Remove a node and all of its descendants.
+    fn clear_inner(&mut self, loc_code: T, internal: &mut HashSet<T>) {
+        self.content.remove(&loc_code);
+        if internal.remove(&loc_code) {
+            loc_code
+                .children()
+                .for_each(|code| self.clear_inner(code, internal));
+        }
+    }
+}
+
+impl<T, D> Octree<T, D>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
     /// Transform an Octree of data D into an Octree of data U, provided that
     /// U implement From<D>
     pub fn transform<U: From<D> + Send + Sync>(self) -> Octree<T, U> {
@@ -164,6 +541,8 @@ where
                 .map(|(loc_code, data)| (loc_code, data.transform::<U>()))
                 .collect::<HashMap<T, OctreeNode<U>>>(),
             max_depth: self.max_depth,
+            world_transform: self.world_transform,
+            marker: PhantomData,
         }
     }
 
@@ -176,6 +555,8 @@ where
                 .map(|(loc_code, data)| (loc_code, data.transform_fn(&function)))
                 .collect::<HashMap<T, OctreeNode<U>>>(),
             max_depth: self.max_depth,
+            world_transform: self.world_transform,
+            marker: PhantomData,
         }
     }
 
@@ -191,63 +572,13 @@ where
                 .map(|(loc_code, data)| (loc_code, function(loc_code, data)))
                 .collect::<HashMap<T, OctreeNode<U>>>(),
             max_depth: self.max_depth,
+            world_transform: self.world_transform,
+            marker: PhantomData,
         }
     }
-
-    /// Internal function for recursively merging AABB.
-    /// Returns a HashSet containing all the node that are affected by the merging, not all new nodes
-    /// These affected nodes can be scheduled to merge data outside here
-    fn merge_inner(
-        &mut self,
-        aabb: AABB,
-        data: D,
-        center: (f64, f64, f64),
-        depth: u32,
-        loc_code: T,
-    ) -> HashSet<T> {
-        let blocks = aabb.explode(center);
-        let max_depth = self.max_depth;
-
-        let (fitting, subdivisables): (Vec<AABB>, Vec<AABB>) = blocks
-            .into_iter()
-            .partition(|aabb| aabb.fit_in(depth, max_depth));
-
-        let mut codes: Vec<T> = fitting
-            .into_iter()
-            .map(|elem| {
-                self.insert(
-                    loc_code << T::three() | elem.orientation,
-                    OctreeNode::new(data),
-                )
-            })
-            .map(|loc_code| loc_code >> T::three())
-            .collect();
-
-        codes.extend(if depth != self.max_depth {
-            subdivisables
-                .into_iter()
-                .map(|aabb| {
-                    let new_loc_code = (loc_code << T::three()) | aabb.orientation;
-                    let new_center = aabb.orientation.make_new_center(new_loc_code, center);
-                    self.merge_inner(
-                        aabb.with_orientation(Orientation::N),
-                        data,
-                        new_center,
-                        depth + 1,
-                        new_loc_code,
-                    )
-                })
-                .flatten()
-                .collect()
-        } else {
-            Vec::<T>::default()
-        });
-
-        codes.into_iter().collect::<HashSet<T>>()
-    }
 }
 
-impl<'a, L, D> Octree<L, D>
+impl<L, D> Octree<L, D>
 where
     L: LocCode,
     D: Send + Sync,
@@ -257,9 +588,71 @@ where
         path: U,
         max_depth: u32,
         optimal: crate::dot_vox::ConversionType,
-    ) -> Result<Vec<Octree<L, u32>>, &'static str> {
-        let vox = dot_vox::load(path.as_ref())?;
-        let octrees: Vec<Octree<L, u32>> = crate::dot_vox::vox_to_octrees(vox, max_depth, optimal);
-        Ok(octrees)
+    ) -> Result<Vec<Octree<L, u32>>, OctreeError> {
+        let vox = dot_vox::load(path.as_ref())
+            .map_err(|error| OctreeError::VoxParse(error.to_string()))?;
+        crate::dot_vox::vox_to_octrees(vox, max_depth, optimal)
+    }
+}
+
+#[cfg(all(test, feature = "dot_tree"))]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    #[test]
+    fn read_bad_codes() {
+        // Zero, a sentinel off a level boundary and a code deeper than
+        // max_depth.
+        let path = std::env::temp_dir().join(format!("bad-codes-{}.tree", std::process::id()));
+        for loc_code in &[0_u32, 0b11, u32::from_coords(1, 1, 1, 3)] {
+            let mut content = HashMap::new();
+            content.insert(*loc_code, OctreeNode::new(1_u8));
+            let mut buffer = Header::new::<u32, u8>(2, "").to_bytes();
+            let mut encoder = ZlibEncoder::new(&mut buffer, Compression::default());
+            bincode::serialize_into(&mut encoder, &(content, 2_u32, Option::<u8>::None)).unwrap();
+            encoder.finish().unwrap();
+            std::fs::write(&path, buffer).unwrap();
+            let read = Octree::<u32, u8>::load_from_file(&path);
+            assert!(matches!(read, Err(OctreeError::Decode(_))));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stream_in_memory() {
+        let mut tree: Octree<u16, u32> = Octree::new(4);
+        tree.merge(AABB::new(0.25, 0.0, 0.5, 1.0, 0.75, 1.0), 0xff00ff)
+            .unwrap();
+        let mut buffer = vec![];
+        tree.write_to(&mut buffer).unwrap();
+        let read = Octree::<u16, u32>::read_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.size(), tree.size());
+        for (code, node) in tree.iter() {
+            let code = Morton::new(code).unwrap();
+            assert_eq!(read.lookup(code).map(|node| node.data), Some(node.data));
+        }
+        let truncated = Octree::<u16, u32>::read_from(&buffer[..buffer.len() / 2]);
+        assert!(matches!(truncated, Err(OctreeError::Decode(_))));
+        let header_only = Octree::<u16, u32>::read_from(&buffer[..10]);
+        assert!(matches!(header_only, Err(OctreeError::Decode(_))));
+        let mut bad_checksum = buffer.clone();
+        *bad_checksum.last_mut().unwrap() ^= 1;
+        let read = Octree::<u16, u32>::read_from(bad_checksum.as_slice());
+        assert!(matches!(read, Err(OctreeError::Decode(_))));
+    }
+
+    #[test]
+    fn read_unbuffered() {
+        let directory = std::env::temp_dir().join(format!("unbuffered-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("tree.tree");
+        let tree: Octree<u32, u8> = Octree::from_voxels(3, vec![(7, 7, 7, 2)]).unwrap();
+        tree.write_to_tagged(File::create(&path).unwrap(), "material")
+            .unwrap();
+        let read =
+            Octree::<u32, u8>::read_from_tagged(File::open(&path).unwrap(), "material").unwrap();
+        assert_eq!(read.leaf_at((7, 7, 7)).map(|(_, node)| node.data), Some(2));
+        std::fs::remove_dir_all(directory).unwrap();
     }
 }
@@ -1,169 +1,268 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::aabb::PlaneAxis;
 use crate::LocCode;
-use std::ops::BitOr;
-
-// TODO: Make u8 not rely on enum position
-#[derive(Debug, Clone, Copy)]
-pub enum Orientation {
-    LBU,
-    LFU,
-    LFD,
-    LBD,
-    RBD,
-    RFD,
-    RFU,
-    RBU,
-    N,
-    L,
-    R,
-    F,
-    B,
-    U,
-    D,
-    LF,
-    LB,
-    RF,
-    RB,
-    FU,
-    FD,
-    BU,
-    BD,
-    LU,
-    LD,
-    RU,
-    RD,
+
+/// Octant of a node inside its parent, stored as one bit per axis set for
+/// the upper half: `4` for R (x), `2` for U (y) and `1` for F (z).
+///
+/// The bits are the index of the octant in a LocCode.
+///
+/// This numbering is a breaking change: versions up to 0.5 numbered the
+/// octants LBU, LFU, LFD, LBD, RBD, RFD, RFU, RBU, so LocCodes stored by them,
+/// raw or serialized with the `serialize` feature, name other octants now.
+/// Convert them with `Morton::from_legacy`, or `.tree` files with
+/// `Octree::migrate_file`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Orientation(u8);
+
+const NAMES: [&str; 8] = ["LBD", "LFD", "LBU", "LFU", "RBD", "RFD", "RBU", "RFU"];
+
+/// Error returned when converting an integer above 7 to an `Orientation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOctant(pub u8);
+
+impl fmt::Display for InvalidOctant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not an octant index", self.0)
+    }
 }
 
+impl std::error::Error for InvalidOctant {}
+
 type Center = (f64, f64, f64);
 
 impl Orientation {
+    pub const LBD: Self = Self(0);
+    pub const LFD: Self = Self(1);
+    pub const LBU: Self = Self(2);
+    pub const LFU: Self = Self(3);
+    pub const RBD: Self = Self(4);
+    pub const RFD: Self = Self(5);
+    pub const RBU: Self = Self(6);
+    pub const RFU: Self = Self(7);
+
+    /// Octants of versions up to 0.5, indexed by their old index.
+    pub const LEGACY: [Self; 8] = [
+        Self::LBU,
+        Self::LFU,
+        Self::LFD,
+        Self::LBD,
+        Self::RBD,
+        Self::RFD,
+        Self::RFU,
+        Self::RBU,
+    ];
+
+    /// Octant on the upper or lower side of each axis.
+    pub fn from_axes(x: bool, y: bool, z: bool) -> Self {
+        Self((x as u8) << 2 | (y as u8) << 1 | z as u8)
+    }
+
+    /// Iterate over the 8 octants, in index order.
+    pub fn octants() -> impl Iterator<Item = Self> {
+        (0..8).map(Self)
+    }
+
+    /// Whether the octant is on the upper side of `axis`.
+    pub fn is_upper(self, axis: PlaneAxis) -> bool {
+        self.0 & Self::bit(axis) != 0
+    }
+
+    /// Same octant moved to the upper or lower side of `axis`.
+    pub fn with_axis(self, axis: PlaneAxis, upper: bool) -> Self {
+        if upper {
+            Self(self.0 | Self::bit(axis))
+        } else {
+            Self(self.0 & !Self::bit(axis))
+        }
+    }
+
+    /// Octant on the other side of `axis`.
+    pub fn mirror(self, axis: PlaneAxis) -> Self {
+        Self(self.0 ^ Self::bit(axis))
+    }
+
+    /// Octant diagonally opposite, on the other side of every axis.
+    pub fn opposite(self) -> Self {
+        Self(self.0 ^ 7)
+    }
+
+    /// Direction from the center of the parent to the octant, `-1` or `1`
+    /// on each axis.
+    pub fn sign_vector(self) -> (i64, i64, i64) {
+        let sign = |axis| if self.is_upper(axis) { 1 } else { -1 };
+        (sign(PlaneAxis::X), sign(PlaneAxis::Y), sign(PlaneAxis::Z))
+    }
+
+    fn bit(axis: PlaneAxis) -> u8 {
+        match axis {
+            PlaneAxis::X => 4,
+            PlaneAxis::Y => 2,
+            PlaneAxis::Z => 1,
+        }
+    }
+
     pub fn make_new_center<L>(self, loc_code: L, center: Center) -> Center
     where
         L: LocCode,
     {
-        let offset: f64 = 1.0 / ((2 as u32).pow(loc_code.get_level()) as f64);
-        match self {
-            Self::LBU => (center.0 - offset, center.1 + offset, center.2 - offset),
-            Self::LFU => (center.0 - offset, center.1 + offset, center.2 + offset),
-            Self::LFD => (center.0 - offset, center.1 - offset, center.2 + offset),
-            Self::LBD => (center.0 - offset, center.1 - offset, center.2 - offset),
-            Self::RBD => (center.0 + offset, center.1 - offset, center.2 - offset),
-            Self::RFD => (center.0 + offset, center.1 - offset, center.2 + offset),
-            Self::RFU => (center.0 + offset, center.1 + offset, center.2 + offset),
-            Self::RBU => (center.0 + offset, center.1 + offset, center.2 - offset),
-            _ => center,
+        let offset: f64 = 1.0 / (2_u32.pow(loc_code.get_level()) as f64);
+        let (x, y, z) = self.sign_vector();
+        (
+            center.0 + x as f64 * offset,
+            center.1 + y as f64 * offset,
+            center.2 + z as f64 * offset,
+        )
+    }
+}
+
+impl fmt::Debug for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(NAMES[self.0 as usize])
+    }
+}
+
+/// Formats the octant name, such as `RBU`.
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(NAMES[self.0 as usize])
+    }
+}
+
+impl From<Orientation> for u8 {
+    fn from(orientation: Orientation) -> u8 {
+        orientation.0
+    }
+}
+
+impl TryFrom<u8> for Orientation {
+    type Error = InvalidOctant;
+
+    fn try_from(index: u8) -> Result<Self, InvalidOctant> {
+        if index < 8 {
+            Ok(Self(index))
+        } else {
+            Err(InvalidOctant(index))
         }
     }
 }
 
-/// TODO: Make this TryInto
-impl Into<u8> for Orientation {
-    fn into(self) -> u8 {
+/// Face and edge directions towards the neighbors of a node, corners being
+/// given by `Orientation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    L,
+    R,
+    D,
+    U,
+    B,
+    F,
+    LD,
+    LU,
+    RD,
+    RU,
+    LB,
+    LF,
+    RB,
+    RF,
+    DB,
+    DF,
+    UB,
+    UF,
+}
+
+impl Direction {
+    /// Directions through the 6 faces of a cell.
+    pub const FACES: [Direction; 6] = [Self::L, Self::R, Self::D, Self::U, Self::B, Self::F];
+
+    /// Directions through the 12 edges of a cell.
+    pub const EDGES: [Direction; 12] = [
+        Self::LD,
+        Self::LU,
+        Self::RD,
+        Self::RU,
+        Self::LB,
+        Self::LF,
+        Self::RB,
+        Self::RF,
+        Self::DB,
+        Self::DF,
+        Self::UB,
+        Self::UF,
+    ];
+
+    /// Offset to the neighbor in cells, `-1`, `0` or `1` on each axis.
+    pub fn offset(self) -> (i64, i64, i64) {
         match self {
-            Self::LBU => 0,
-            Self::LFU => 1,
-            Self::LFD => 2,
-            Self::LBD => 3,
-            Self::RBD => 4,
-            Self::RFD => 5,
-            Self::RFU => 6,
-            Self::RBU => 7,
-            _ => 8,
+            Self::L => (-1, 0, 0),
+            Self::R => (1, 0, 0),
+            Self::D => (0, -1, 0),
+            Self::U => (0, 1, 0),
+            Self::B => (0, 0, -1),
+            Self::F => (0, 0, 1),
+            Self::LD => (-1, -1, 0),
+            Self::LU => (-1, 1, 0),
+            Self::RD => (1, -1, 0),
+            Self::RU => (1, 1, 0),
+            Self::LB => (-1, 0, -1),
+            Self::LF => (-1, 0, 1),
+            Self::RB => (1, 0, -1),
+            Self::RF => (1, 0, 1),
+            Self::DB => (0, -1, -1),
+            Self::DF => (0, -1, 1),
+            Self::UB => (0, 1, -1),
+            Self::UF => (0, 1, 1),
+        }
+    }
+
+    /// Direction pointing the other way.
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::L => Self::R,
+            Self::R => Self::L,
+            Self::D => Self::U,
+            Self::U => Self::D,
+            Self::B => Self::F,
+            Self::F => Self::B,
+            Self::LD => Self::RU,
+            Self::LU => Self::RD,
+            Self::RD => Self::LU,
+            Self::RU => Self::LD,
+            Self::LB => Self::RF,
+            Self::LF => Self::RB,
+            Self::RB => Self::LF,
+            Self::RF => Self::LB,
+            Self::DB => Self::UF,
+            Self::DF => Self::UB,
+            Self::UB => Self::DF,
+            Self::UF => Self::DB,
         }
     }
 }
 
-impl BitOr for Orientation {
-    type Output = Self;
-
-    fn bitor(self, rh: Self) -> Self {
-        match (&self, &rh) {
-            // X | Z
-            (Self::L, Self::F) => Self::LF,
-            (Self::L, Self::B) => Self::LB,
-            (Self::R, Self::F) => Self::RF,
-            (Self::R, Self::B) => Self::RB,
-            (Self::F, Self::L) => Self::LF,
-            (Self::F, Self::R) => Self::RF,
-            (Self::B, Self::L) => Self::LB,
-            (Self::B, Self::R) => Self::RB,
-
-            // Z | Y
-            (Self::F, Self::U) => Self::FU,
-            (Self::F, Self::D) => Self::FD,
-            (Self::B, Self::U) => Self::BU,
-            (Self::B, Self::D) => Self::BD,
-            (Self::U, Self::F) => Self::FU,
-            (Self::U, Self::B) => Self::BU,
-            (Self::D, Self::F) => Self::FD,
-            (Self::D, Self::B) => Self::BD,
-
-            // Y | X
-            (Self::L, Self::U) => Self::LU,
-            (Self::L, Self::D) => Self::LD,
-            (Self::R, Self::U) => Self::RU,
-            (Self::R, Self::D) => Self::RD,
-            (Self::U, Self::L) => Self::LU,
-            (Self::U, Self::R) => Self::RU,
-            (Self::D, Self::L) => Self::LD,
-            (Self::D, Self::R) => Self::RD,
-
-            // XZ | Y
-            (Self::LF, Self::U) => Self::LFU,
-            (Self::LF, Self::D) => Self::LFD,
-            (Self::LB, Self::U) => Self::LBU,
-            (Self::LB, Self::D) => Self::LBD,
-            (Self::RF, Self::U) => Self::RFU,
-            (Self::RF, Self::D) => Self::RFD,
-            (Self::RB, Self::U) => Self::RBU,
-            (Self::RB, Self::D) => Self::RBD,
-            (Self::U, Self::LF) => Self::LFU,
-            (Self::U, Self::LB) => Self::LBU,
-            (Self::U, Self::RF) => Self::RFU,
-            (Self::U, Self::RB) => Self::RBU,
-            (Self::D, Self::LF) => Self::LFD,
-            (Self::D, Self::LB) => Self::LBD,
-            (Self::D, Self::RF) => Self::RFD,
-            (Self::D, Self::RB) => Self::RBD,
-
-            // YZ | X
-            (Self::FU, Self::L) => Self::LFU,
-            (Self::FU, Self::R) => Self::RFU,
-            (Self::FD, Self::L) => Self::LFD,
-            (Self::FD, Self::R) => Self::RFD,
-            (Self::BU, Self::L) => Self::LBU,
-            (Self::BU, Self::R) => Self::RBU,
-            (Self::BD, Self::L) => Self::LBD,
-            (Self::BD, Self::R) => Self::RBD,
-            (Self::L, Self::FU) => Self::LFU,
-            (Self::L, Self::FD) => Self::LFD,
-            (Self::L, Self::BU) => Self::LBU,
-            (Self::L, Self::BD) => Self::LBD,
-            (Self::R, Self::FU) => Self::RFU,
-            (Self::R, Self::FD) => Self::RFD,
-            (Self::R, Self::BU) => Self::RBU,
-            (Self::R, Self::BD) => Self::RBD,
-
-            // XY | Z
-            (Self::LU, Self::F) => Self::LFU,
-            (Self::LU, Self::B) => Self::LBU,
-            (Self::LD, Self::F) => Self::LFD,
-            (Self::LD, Self::B) => Self::LBD,
-            (Self::RU, Self::F) => Self::RFU,
-            (Self::RU, Self::B) => Self::RBU,
-            (Self::RD, Self::F) => Self::RFD,
-            (Self::RD, Self::B) => Self::RBD,
-            (Self::F, Self::LU) => Self::LFU,
-            (Self::F, Self::LD) => Self::LFD,
-            (Self::F, Self::RU) => Self::RFU,
-            (Self::F, Self::RD) => Self::RFD,
-            (Self::B, Self::LU) => Self::LBU,
-            (Self::B, Self::LD) => Self::LBD,
-            (Self::B, Self::RU) => Self::RBU,
-            (Self::B, Self::RD) => Self::RBD,
-
-            // Otherwise
-            (Self::N, _) => rh,
-            _ => self,
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn octants() {
+        assert_eq!(Orientation::octants().count(), 8);
+        for (index, octant) in Orientation::octants().enumerate() {
+            assert_eq!(Orientation::try_from(index as u8), Ok(octant));
+            assert_eq!(octant.opposite().opposite(), octant);
+            let (x, y, z) = octant.sign_vector();
+            assert_eq!(octant.opposite().sign_vector(), (-x, -y, -z));
+        }
+        assert_eq!(Orientation::try_from(8), Err(InvalidOctant(8)));
+        assert_eq!(Orientation::from_axes(true, false, true), Orientation::RFD);
+        assert_eq!(Orientation::LBU.mirror(PlaneAxis::Z), Orientation::LFU);
+        assert_eq!(Orientation::RBU.opposite(), Orientation::LFD);
+        assert_eq!(format!("{}", Orientation::RBU), "RBU");
+        for direction in Direction::FACES.iter().chain(Direction::EDGES.iter()) {
+            let (x, y, z) = direction.offset();
+            assert_eq!(direction.opposite().offset(), (-x, -y, -z));
         }
     }
 }
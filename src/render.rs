@@ -5,7 +5,6 @@ use rayon::prelude::*;
 
 use std::{fmt::Debug, hash::Hash};
 
-
 pub struct Model {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
@@ -88,7 +87,7 @@ where
     let data = node.data;
     let mut graph = DiGraphMap::new();
     let center = loc_code.get_center_u32();
-    let offset = (2 as u32).pow(32 - loc_code.get_level());
+    let offset = loc_code.get_offset();
     let color = [data.rgb().r, data.rgb().g, data.rgb().b, data.a];
 
     let [lbd, lfd, lbu, lfu, rbd, rfd, rbu, rfu] = get_angles(center, offset);
@@ -0,0 +1,185 @@
+//
+// section.rs in generic_octree
+// Description:
+// Planar cross-sections of an Octree as 2D grids and images.
+//
+
+use std::io::{self, Write};
+
+use crate::aabb::{Plane, PlaneAxis};
+use crate::{LocCode, Octree, OctreeStorage};
+
+/// 2D grid of the data found on a plane through a tree.
+///
+/// Columns and rows follow the two other axes in `x`, `y`, `z` order, so a
+/// section along `X` has `y` columns and `z` rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossSection<D> {
+    width: usize,
+    height: usize,
+    cells: Vec<Option<D>>,
+}
+
+impl<D> CrossSection<D> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Data at column `u` and row `v`, `None` for empty space.
+    pub fn get(&self, u: usize, v: usize) -> Option<&D> {
+        if u < self.width && v < self.height {
+            self.cells[v * self.width + u].as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Rows from the highest one down, as they appear in an image.
+    fn image_rows(&self) -> impl Iterator<Item = &[Option<D>]> {
+        self.cells.chunks(self.width.max(1)).rev()
+    }
+
+    /// Write the section as a binary PPM image, coloring each cell.
+    pub fn write_ppm<W, F>(&self, mut writer: W, color: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(Option<&D>) -> [u8; 3],
+    {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for row in self.image_rows() {
+            for cell in row {
+                writer.write_all(&color(cell.as_ref()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the section as a binary PGM image, shading each cell.
+    pub fn write_pgm<W, F>(&self, mut writer: W, gray: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(Option<&D>) -> u8,
+    {
+        write!(writer, "P5\n{} {}\n255\n", self.width, self.height)?;
+        for row in self.image_rows() {
+            let row: Vec<u8> = row.iter().map(|cell| gray(cell.as_ref())).collect();
+            writer.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// Render the section as lines of characters, handy for test snapshots.
+    pub fn to_ascii<F>(&self, character: F) -> String
+    where
+        F: Fn(Option<&D>) -> char,
+    {
+        let mut ascii = String::with_capacity((self.width + 1) * self.height);
+        for row in self.image_rows() {
+            ascii.extend(row.iter().map(|cell| character(cell.as_ref())));
+            ascii.push('\n');
+        }
+        ascii
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Sample the tree on a plane at `position` along `axis`, in the unit cube
+    /// of the tree, with `resolution` cells per side.
+    ///
+    /// A plane outside of `[0, 1)` misses the tree, so every cell is empty.
+    pub fn cross_section(
+        &self,
+        axis: PlaneAxis,
+        position: f64,
+        resolution: usize,
+    ) -> CrossSection<D> {
+        if !(0.0..1.0).contains(&position) {
+            return CrossSection {
+                width: resolution,
+                height: resolution,
+                cells: vec![None; resolution * resolution],
+            };
+        }
+        let side = 1_u64 << self.max_depth();
+        let depth = (position * side as f64) as u64;
+        let sample = |index: usize| ((index as u64 * 2 + 1) * side) / (resolution as u64 * 2);
+        let mut cells = Vec::with_capacity(resolution * resolution);
+        for v in 0..resolution {
+            for u in 0..resolution {
+                let (u, v) = (sample(u), sample(v));
+                let voxel = match axis {
+                    PlaneAxis::X => (depth, u, v),
+                    PlaneAxis::Y => (u, depth, v),
+                    PlaneAxis::Z => (u, v, depth),
+                };
+                cells.push(self.leaf_at(voxel).map(|(_, node)| node.data));
+            }
+        }
+        CrossSection {
+            width: resolution,
+            height: resolution,
+            cells,
+        }
+    }
+
+    /// Sample the tree on `plane`, see `cross_section`.
+    pub fn plane_section(&self, plane: Plane, resolution: usize) -> CrossSection<D> {
+        self.cross_section(plane.axis(), plane.position(), resolution)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    #[test]
+    fn ascii() {
+        let mut tree: Octree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.25, 1.0), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.75, 0.75, 0.0, 1.0, 1.0, 0.5), 2)
+            .unwrap();
+        let section = tree.cross_section(PlaneAxis::Z, 0.25, 4);
+        let ascii = section.to_ascii(|cell| match cell {
+            Some(1) => '#',
+            Some(_) => '+',
+            None => '.',
+        });
+        assert_eq!(ascii, "...+\n....\n....\n##..\n");
+        assert_eq!(section.get(0, 0), Some(&1));
+        assert_eq!(tree.cross_section(PlaneAxis::Z, 0.75, 2).get(1, 1), None);
+        for position in &[1.0, 1.5, -0.25, f64::NAN] {
+            let outside = tree.cross_section(PlaneAxis::Z, *position, 4);
+            assert_eq!(outside.width(), 4);
+            assert!((0..4).all(|u| (0..4).all(|v| outside.get(u, v).is_none())));
+        }
+    }
+
+    #[test]
+    fn images() {
+        let mut tree: Octree<u32, u8> = Octree::new(1);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 200)
+            .unwrap();
+        let section = tree.plane_section(Plane::new(0.0, PlaneAxis::X), 2);
+        let mut pgm = vec![];
+        section
+            .write_pgm(&mut pgm, |cell| *cell.unwrap_or(&0))
+            .unwrap();
+        assert_eq!(pgm, b"P5\n2 2\n255\n\0\0\xc8\0".to_vec());
+        let mut ppm = vec![];
+        section
+            .write_ppm(&mut ppm, |cell| [*cell.unwrap_or(&0), 0, 1])
+            .unwrap();
+        assert_eq!(ppm.len(), 11 + 12);
+    }
+}
@@ -0,0 +1,410 @@
+//
+// shared_storage.rs in generic_octree
+// Description:
+// Persistent storage sharing its structure between snapshots.
+//
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use hashbrown::HashMap;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::slice;
+use std::sync::Arc;
+
+use crate::{Children, LocCode, Octree, OctreeNode, OctreeStorage};
+
+/// Node of the trie, following the octants of the codes from the root.
+#[derive(Debug, Clone)]
+struct Trie<D> {
+    node: Option<OctreeNode<D>>,
+    children: [Option<Arc<Trie<D>>>; 8],
+}
+
+impl<D> Default for Trie<D> {
+    fn default() -> Self {
+        Self {
+            node: None,
+            children: Default::default(),
+        }
+    }
+}
+
+impl<D> Trie<D> {
+    fn is_empty(&self) -> bool {
+        self.node.is_none() && self.children.iter().all(Option::is_none)
+    }
+
+    fn count(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .flatten()
+            .map(|child| child.count())
+            .sum::<usize>()
+    }
+}
+
+/// Octants leading from the root to `loc_code`.
+fn path<L: LocCode>(loc_code: L) -> Vec<usize> {
+    (1..=loc_code.get_depth())
+        .filter_map(|depth| loc_code.ancestor_at(depth)?.octant())
+        .map(|octant| u8::from(octant) as usize)
+        .collect()
+}
+
+fn remove_path<D: Clone>(trie: &mut Arc<Trie<D>>, path: &[usize]) -> Option<OctreeNode<D>> {
+    let trie = Arc::make_mut(trie);
+    match path.split_first() {
+        None => trie.node.take(),
+        Some((&octant, rest)) => {
+            let child = trie.children[octant].as_mut()?;
+            let removed = remove_path(child, rest);
+            if child.is_empty() {
+                trie.children[octant] = None;
+            }
+            removed
+        }
+    }
+}
+
+/// Storage keeping the nodes in a trie of reference counted nodes, so that
+/// cloning it is O(1).
+///
+/// Clones share every node until one of them is edited, which then only
+/// copies the path from the root to the edited code. Lookups and edits are
+/// O(depth) and nodes are iterated in Morton order.
+#[derive(Debug)]
+pub struct SharedStorage<L, D> {
+    root: Arc<Trie<D>>,
+    len: usize,
+    marker: PhantomData<L>,
+}
+
+/// Octree stored in a `SharedStorage`, to take snapshots of.
+pub type SharedOctree<L, D> = Octree<L, D, SharedStorage<L, D>>;
+
+/// Node which differs between two versions of a tree.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeChange<'a, L, D> {
+    pub loc_code: L,
+    /// Node in the older version, `None` if it was added.
+    pub before: Option<&'a OctreeNode<D>>,
+    /// Node in the newer version, `None` if it was removed.
+    pub after: Option<&'a OctreeNode<D>>,
+}
+
+/// Children slots of a trie node.
+type Subtries<'a, D> = slice::Iter<'a, Option<Arc<Trie<D>>>>;
+
+/// Nodes of a `SharedStorage`, in Morton order.
+#[derive(Debug, Clone)]
+pub struct SharedNodes<'a, L, D> {
+    /// Trie node to visit next.
+    next: Option<(L, &'a Trie<D>)>,
+    /// Children left to visit of each trie node on the path to `next`.
+    stack: Vec<(Children<L>, Subtries<'a, D>)>,
+}
+
+impl<'a, L, D> SharedNodes<'a, L, D> {
+    fn new(root: Option<(L, &'a Trie<D>)>) -> Self {
+        Self {
+            next: root,
+            stack: vec![],
+        }
+    }
+}
+
+impl<'a, L: LocCode, D> Iterator for SharedNodes<'a, L, D> {
+    type Item = (L, &'a OctreeNode<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((loc_code, trie)) = self.next.take() {
+                if trie.children.iter().any(Option::is_some) {
+                    self.stack.push((loc_code.children(), trie.children.iter()));
+                }
+                if let Some(node) = &trie.node {
+                    return Some((loc_code, node));
+                }
+                continue;
+            }
+            let (codes, tries) = self.stack.last_mut()?;
+            self.next = codes
+                .zip(tries)
+                .find_map(|(child, trie)| trie.as_deref().map(|trie| (child, trie)));
+            if self.next.is_none() {
+                self.stack.pop();
+            }
+        }
+    }
+}
+
+fn diff_tries<'a, L, D>(
+    loc_code: L,
+    before: Option<&'a Arc<Trie<D>>>,
+    after: Option<&'a Arc<Trie<D>>>,
+    changes: &mut Vec<NodeChange<'a, L, D>>,
+) where
+    L: LocCode,
+    D: PartialEq,
+{
+    if let (Some(before), Some(after)) = (before, after) {
+        if Arc::ptr_eq(before, after) {
+            return;
+        }
+    }
+    let node = |trie: Option<&'a Arc<Trie<D>>>| trie.and_then(|trie| trie.node.as_ref());
+    let (old, new) = (node(before), node(after));
+    if old.map(|node| &node.data) != new.map(|node| &node.data) {
+        changes.push(NodeChange {
+            loc_code,
+            before: old,
+            after: new,
+        });
+    }
+    let child = |trie: Option<&'a Arc<Trie<D>>>, octant: usize| {
+        trie.and_then(|trie| trie.children[octant].as_ref())
+    };
+    for (octant, code) in loc_code.children().enumerate() {
+        let (before, after) = (child(before, octant), child(after, octant));
+        if before.is_some() || after.is_some() {
+            diff_tries(code, before, after, changes);
+        }
+    }
+}
+
+impl<L, D> SharedStorage<L, D> {
+    /// Whether both storages are still the same version, without comparing
+    /// their nodes.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+}
+
+impl<L: LocCode, D> SharedStorage<L, D> {
+    fn trie(&self, loc_code: L) -> Option<&Trie<D>> {
+        path(loc_code)
+            .into_iter()
+            .try_fold(&*self.root, |trie, octant| trie.children[octant].as_deref())
+    }
+
+    /// Nodes which differ from the `older` version, in Morton order.
+    ///
+    /// Subtrees still shared by both versions are skipped, so this is
+    /// proportional to the edits made since `older` was cloned.
+    pub fn diff<'a>(&'a self, older: &'a Self) -> Vec<NodeChange<'a, L, D>>
+    where
+        D: PartialEq,
+    {
+        let mut changes = vec![];
+        diff_tries(L::root(), Some(&older.root), Some(&self.root), &mut changes);
+        changes
+    }
+}
+
+impl<L, D> Clone for SharedStorage<L, D> {
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<L, D> Default for SharedStorage<L, D> {
+    fn default() -> Self {
+        Self {
+            root: Arc::new(Trie::default()),
+            len: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<L: LocCode, D: Clone> FromIterator<(L, OctreeNode<D>)> for SharedStorage<L, D> {
+    fn from_iter<I: IntoIterator<Item = (L, OctreeNode<D>)>>(nodes: I) -> Self {
+        let mut storage = Self::default();
+        for (loc_code, node) in nodes {
+            storage.insert(loc_code, node);
+        }
+        storage
+    }
+}
+
+impl<L: LocCode, D: Clone> OctreeStorage<L, D> for SharedStorage<L, D> {
+    fn with_capacity(_capacity: usize) -> Self {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, loc_code: &L) -> Option<&OctreeNode<D>> {
+        self.trie(*loc_code)?.node.as_ref()
+    }
+
+    fn insert(&mut self, loc_code: L, node: OctreeNode<D>) -> Option<OctreeNode<D>> {
+        let mut trie = Arc::make_mut(&mut self.root);
+        for octant in path(loc_code) {
+            trie = Arc::make_mut(trie.children[octant].get_or_insert_with(Default::default));
+        }
+        let replaced = trie.node.replace(node);
+        if replaced.is_none() {
+            self.len += 1;
+        }
+        replaced
+    }
+
+    fn remove(&mut self, loc_code: &L) -> Option<OctreeNode<D>> {
+        // Avoid copying the path of a code which is not there.
+        self.get(loc_code)?;
+        let removed = remove_path(&mut self.root, &path(*loc_code));
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    type Iter<'a>
+        = SharedNodes<'a, L, D>
+    where
+        Self: 'a;
+
+    type Range<'a>
+        = SharedNodes<'a, L, D>
+    where
+        Self: 'a;
+
+    /// Iterate over the nodes in Morton order.
+    fn iter(&self) -> Self::Iter<'_> {
+        SharedNodes::new(Some((L::root(), &*self.root)))
+    }
+
+    /// Bytes of every trie node, shared ones included.
+    fn heap_bytes(&self) -> usize {
+        self.root.count() * (size_of::<Trie<D>>() + 2 * size_of::<usize>())
+    }
+
+    fn range(&self, root: L) -> Self::Range<'_> {
+        SharedNodes::new(self.trie(root).map(|trie| (root, trie)))
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<L, D> Serialize for SharedStorage<L, D>
+where
+    L: LocCode + Serialize,
+    D: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(SharedNodes::new(Some((L::root(), &*self.root))))
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de, L, D> Deserialize<'de> for SharedStorage<L, D>
+where
+    L: LocCode + Deserialize<'de>,
+    D: Clone + Deserialize<'de>,
+{
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let nodes = Vec::<(L, OctreeNode<D>)>::deserialize(deserializer)?;
+        Ok(nodes.into_iter().collect())
+    }
+}
+
+impl<L, D> SharedOctree<L, D>
+where
+    L: LocCode,
+    D: Clone + Send + Sync,
+{
+    /// Frozen copy of the tree in O(1), unaffected by later edits of either
+    /// of them and cheap to send to another thread to save or read.
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    /// Nodes which differ from the `older` snapshot, in Morton order.
+    pub fn diff<'a>(&'a self, older: &'a Self) -> Vec<NodeChange<'a, L, D>>
+    where
+        D: PartialEq,
+    {
+        self.content.diff(&older.content)
+    }
+}
+
+impl<L, D> From<Octree<L, D>> for SharedOctree<L, D>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    fn from(tree: Octree<L, D>) -> Self {
+        tree.convert_storage()
+    }
+}
+
+impl<L, D> From<SharedOctree<L, D>> for Octree<L, D, HashMap<L, OctreeNode<D>>>
+where
+    L: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+{
+    fn from(tree: SharedOctree<L, D>) -> Self {
+        tree.convert_storage()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Morton, AABB};
+
+    #[test]
+    fn snapshot_isolation() {
+        let mut tree: SharedOctree<u32, u8> = Octree::new(3);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        let snapshot = tree.snapshot();
+        assert!(snapshot.storage().ptr_eq(tree.storage()));
+        tree.merge(AABB::new(0.5, 0.5, 0.5, 1.0, 1.0, 0.75), 2)
+            .unwrap();
+        let reader = std::thread::spawn(move || {
+            (
+                snapshot.size(),
+                snapshot.leaf_at((7, 7, 4)).map(|(_, node)| node.data),
+            )
+        });
+        assert_eq!(reader.join().unwrap(), (1, None));
+        assert_eq!(tree.size(), 5);
+        assert_eq!(tree.leaf_at((7, 7, 4)).map(|(_, node)| node.data), Some(2));
+        let root = Morton::new(u32::from_coords(0, 0, 0, 1)).unwrap();
+        assert_eq!(tree.lookup(root).map(|node| node.data), Some(1));
+    }
+
+    #[test]
+    fn diff() {
+        let mut tree: SharedOctree<u32, u8> = Octree::new(2);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 1.0, 0.5, 1.0), 1)
+            .unwrap();
+        let older = tree.snapshot();
+        tree.merge(AABB::new(0.0, 0.5, 0.0, 0.25, 0.75, 0.25), 2)
+            .unwrap();
+        let changes = tree.diff(&older);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].loc_code, u32::from_coords(0, 2, 0, 2));
+        assert!(changes[0].before.is_none());
+        assert_eq!(changes[0].after.map(|node| node.data), Some(2));
+        assert!(older.diff(&older).is_empty());
+
+        let codes: Vec<u32> = tree.iter().map(|(code, _)| code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort_by_key(|code| crate::linear_storage::morton_key(*code));
+        assert_eq!(codes, sorted);
+        let back: Octree<u32, u8> = Octree::from(tree.clone());
+        assert_eq!(back.size(), tree.size());
+    }
+}
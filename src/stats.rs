@@ -0,0 +1,139 @@
+//
+// stats.rs in generic_octree
+// Description:
+// Memory usage and structure statistics of an Octree.
+//
+
+use crate::{LocCode, Octree, OctreeStorage};
+
+/// Report returned by `Octree::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctreeStats<D> {
+    /// Stored leaves at each depth, from the root to `max_depth`.
+    pub leaves_per_depth: Vec<usize>,
+    /// Nodes having descendants at each depth, from the root to `max_depth`.
+    pub internal_per_depth: Vec<usize>,
+    /// Number of leaves holding each distinct value, in order of appearance.
+    pub leaves_per_value: Vec<(D, usize)>,
+    /// Estimated heap bytes used by the storage.
+    pub heap_bytes: usize,
+    /// Voxels at `max_depth` covered by the leaves.
+    pub voxels: u128,
+    /// Voxels at `max_depth` of the whole cube, as a dense grid would hold.
+    pub dense_voxels: u128,
+    pub shallowest_leaf: Option<u32>,
+    pub deepest_leaf: Option<u32>,
+}
+
+impl<D> OctreeStats<D> {
+    /// Number of stored leaves.
+    pub fn leaves(&self) -> usize {
+        self.leaves_per_depth.iter().sum()
+    }
+
+    /// Average number of voxels each leaf stands for, `1.0` meaning nothing
+    /// was collapsed.
+    pub fn voxels_per_leaf(&self) -> f64 {
+        match self.leaves() {
+            0 => 1.0,
+            leaves => self.voxels as f64 / leaves as f64,
+        }
+    }
+
+    /// Voxels of the dense grid per stored leaf, how many times fewer nodes
+    /// the tree holds than a dense grid at `max_depth`. Infinite for an empty
+    /// tree.
+    pub fn collapse_ratio(&self) -> f64 {
+        self.dense_voxels as f64 / self.leaves() as f64
+    }
+
+    /// Part of the dense grid covered by leaves.
+    pub fn fill_ratio(&self) -> f64 {
+        self.voxels as f64 / self.dense_voxels as f64
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Gather statistics over every node, in a single pass over the storage.
+    ///
+    /// Counting the leaves of each value is O(leaves * values), slow for
+    /// trees holding many distinct values.
+    pub fn stats(&self) -> OctreeStats<D> {
+        let max_depth = self.max_depth();
+        let levels = max_depth as usize + 1;
+        let mut stats = OctreeStats {
+            leaves_per_depth: vec![0; levels],
+            internal_per_depth: vec![0; levels],
+            leaves_per_value: vec![],
+            heap_bytes: self.content.heap_bytes(),
+            voxels: 0,
+            dense_voxels: 1 << (3 * max_depth),
+            shallowest_leaf: None,
+            deepest_leaf: None,
+        };
+        for (code, node) in self.content.iter() {
+            let depth = code.get_depth();
+            if let Some(count) = stats.leaves_per_depth.get_mut(depth as usize) {
+                *count += 1;
+                stats.voxels += 1 << (3 * (max_depth - depth));
+            }
+            stats.shallowest_leaf = Some(stats.shallowest_leaf.map_or(depth, |d| d.min(depth)));
+            stats.deepest_leaf = Some(stats.deepest_leaf.map_or(depth, |d| d.max(depth)));
+            match stats
+                .leaves_per_value
+                .iter_mut()
+                .find(|(data, _)| *data == node.data)
+            {
+                Some((_, count)) => *count += 1,
+                None => stats.leaves_per_value.push((node.data, 1)),
+            }
+        }
+        for code in self.ancestors() {
+            if let Some(count) = stats.internal_per_depth.get_mut(code.get_depth() as usize) {
+                *count += 1;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AABB;
+
+    #[test]
+    fn stats() {
+        let mut tree: Octree<u32, u8> = Octree::new(2);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.75, 0.75, 0.75, 1.0, 1.0, 1.0), 2)
+            .unwrap();
+        tree.merge(AABB::new(0.5, 0.75, 0.75, 0.75, 1.0, 1.0), 1)
+            .unwrap();
+        let stats = tree.stats();
+        assert_eq!(stats.leaves_per_depth, vec![0, 1, 2]);
+        assert_eq!(stats.internal_per_depth, vec![1, 1, 0]);
+        let mut values = stats.leaves_per_value.clone();
+        values.sort();
+        assert_eq!(values, vec![(1, 2), (2, 1)]);
+        assert_eq!(stats.leaves(), 3);
+        assert_eq!(stats.voxels, 10);
+        assert_eq!(stats.dense_voxels, 64);
+        assert_eq!(stats.voxels_per_leaf(), 10.0 / 3.0);
+        assert_eq!(stats.collapse_ratio(), 64.0 / 3.0);
+        assert_eq!(
+            (stats.shallowest_leaf, stats.deepest_leaf),
+            (Some(1), Some(2))
+        );
+        assert!(stats.heap_bytes >= 3 * std::mem::size_of::<(u32, u8)>());
+        let empty = Octree::<u32, u8>::new(2).stats();
+        assert_eq!(empty.deepest_leaf, None);
+        assert_eq!(empty.collapse_ratio(), f64::INFINITY);
+    }
+}
@@ -0,0 +1,180 @@
+//
+// storage.rs in generic_octree
+// Description:
+// Storage backends holding the nodes of an Octree.
+//
+
+use hashbrown::{hash_map, HashMap};
+use std::mem::size_of;
+
+use crate::{LocCode, OctreeNode};
+
+/// Map from LocCode to node holding the leaves of an Octree.
+///
+/// `Octree` only needs these operations, so the memory layout can be picked
+/// per use case through its `S` type parameter.
+///
+/// Each storage names its own iterator types, so they are not boxed.
+pub trait OctreeStorage<L: LocCode, D>: Default {
+    /// Iterator over the `(LocCode, node)` pairs of every node.
+    type Iter<'a>: Iterator<Item = (L, &'a OctreeNode<D>)>
+    where
+        Self: 'a,
+        D: 'a;
+
+    /// Iterator over the `(LocCode, node)` pairs of a subtree.
+    type Range<'a>: Iterator<Item = (L, &'a OctreeNode<D>)>
+    where
+        Self: 'a,
+        D: 'a;
+
+    /// Empty storage with room for `capacity` nodes.
+    fn with_capacity(capacity: usize) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, loc_code: &L) -> Option<&OctreeNode<D>>;
+
+    fn contains(&self, loc_code: &L) -> bool {
+        self.get(loc_code).is_some()
+    }
+
+    /// Insert a node, returning the one it replaces.
+    fn insert(&mut self, loc_code: L, node: OctreeNode<D>) -> Option<OctreeNode<D>>;
+
+    fn remove(&mut self, loc_code: &L) -> Option<OctreeNode<D>>;
+
+    /// Iterate over every node, in no particular order.
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Estimated bytes allocated on the heap, not counting the heap data of
+    /// the nodes themselves.
+    fn heap_bytes(&self) -> usize {
+        self.len() * size_of::<(L, OctreeNode<D>)>()
+    }
+
+    /// Iterate over the nodes of the subtree of `root`, itself included.
+    ///
+    /// `LinearStorage` and `SharedStorage` go straight to the subtree, but
+    /// the `HashMap` storage tests every node, in O(n).
+    fn range(&self, root: L) -> Self::Range<'_>;
+}
+
+/// Nodes of a `HashMap` storage, in no particular order.
+#[derive(Debug, Clone)]
+pub struct HashMapNodes<'a, L, D>(hash_map::Iter<'a, L, OctreeNode<D>>);
+
+impl<'a, L: Copy, D> Iterator for HashMapNodes<'a, L, D> {
+    type Item = (L, &'a OctreeNode<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(loc_code, node)| (*loc_code, node))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// Nodes of a subtree of a `HashMap` storage, found by testing every node of
+/// the map: iterating it is O(n) whatever the size of the subtree.
+#[derive(Debug, Clone)]
+pub struct HashMapRange<'a, L, D> {
+    root: L,
+    nodes: HashMapNodes<'a, L, D>,
+}
+
+impl<'a, L: LocCode, D> Iterator for HashMapRange<'a, L, D> {
+    type Item = (L, &'a OctreeNode<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let root = self.root;
+        self.nodes
+            .find(|(loc_code, _)| *loc_code == root || root.is_ancestor_of(*loc_code))
+    }
+}
+
+impl<L, D> OctreeStorage<L, D> for HashMap<L, OctreeNode<D>>
+where
+    L: LocCode,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity(capacity)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn get(&self, loc_code: &L) -> Option<&OctreeNode<D>> {
+        HashMap::get(self, loc_code)
+    }
+
+    fn contains(&self, loc_code: &L) -> bool {
+        self.contains_key(loc_code)
+    }
+
+    fn insert(&mut self, loc_code: L, node: OctreeNode<D>) -> Option<OctreeNode<D>> {
+        HashMap::insert(self, loc_code, node)
+    }
+
+    fn remove(&mut self, loc_code: &L) -> Option<OctreeNode<D>> {
+        HashMap::remove(self, loc_code)
+    }
+
+    type Iter<'a>
+        = HashMapNodes<'a, L, D>
+    where
+        Self: 'a;
+
+    type Range<'a>
+        = HashMapRange<'a, L, D>
+    where
+        Self: 'a;
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMapNodes(HashMap::iter(self))
+    }
+
+    /// Buckets of the table plus one control byte each.
+    fn heap_bytes(&self) -> usize {
+        self.capacity() * (size_of::<(L, OctreeNode<D>)>() + 1)
+    }
+
+    /// Scans every node of the map, in O(n), unlike the other storages.
+    fn range(&self, root: L) -> Self::Range<'_> {
+        HashMapRange {
+            root,
+            nodes: OctreeStorage::iter(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Octree, AABB};
+
+    #[test]
+    fn range() {
+        let mut tree: Octree<u32, u8> = Octree::new(2);
+        tree.merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 1)
+            .unwrap();
+        tree.merge(AABB::new(0.75, 0.75, 0.75, 1.0, 1.0, 1.0), 2)
+            .unwrap();
+        let storage = tree.storage();
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.range(u32::root()).count(), 2);
+        let upper = u32::from_coords(1, 1, 1, 1);
+        let nodes: Vec<(u32, u8)> = storage
+            .range(upper)
+            .map(|(code, node)| (code, node.data))
+            .collect();
+        assert_eq!(nodes, vec![(u32::from_coords(3, 3, 3, 2), 2)]);
+        assert_eq!(storage.range(u32::from_coords(0, 0, 0, 2)).count(), 0);
+    }
+}
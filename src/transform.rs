@@ -0,0 +1,238 @@
+//
+// transform.rs in generic_octree
+// Description:
+// Placement of an Octree in world space.
+//
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serialize")]
+use std::convert::TryFrom;
+
+use crate::{LocCode, Morton, Octree, OctreeError, OctreeNode, OctreeStorage, AABB};
+
+/// Maps the unit cube of a tree to a cube of world space, starting at
+/// `origin` and `extent` units wide on every axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(try_from = "RawWorldTransform")
+)]
+pub struct WorldTransform {
+    origin: (f64, f64, f64),
+    extent: f64,
+}
+
+/// Fields of a deserialized `WorldTransform`, before `extent` is checked.
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+struct RawWorldTransform {
+    origin: (f64, f64, f64),
+    extent: f64,
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<RawWorldTransform> for WorldTransform {
+    type Error = OctreeError;
+
+    fn try_from(raw: RawWorldTransform) -> Result<Self, OctreeError> {
+        Self::try_new(raw.origin, raw.extent)
+    }
+}
+
+impl Default for WorldTransform {
+    /// The unit cube itself.
+    fn default() -> Self {
+        Self::new((0.0, 0.0, 0.0), 1.0)
+    }
+}
+
+impl WorldTransform {
+    /// Panics if `extent` is not a positive finite number, see `try_new`.
+    pub fn new(origin: (f64, f64, f64), extent: f64) -> Self {
+        Self::try_new(origin, extent).expect("the world extent must be positive")
+    }
+
+    /// Create a transform, failing if `extent` is not a positive finite
+    /// number.
+    pub fn try_new(origin: (f64, f64, f64), extent: f64) -> Result<Self, OctreeError> {
+        if extent > 0.0 && extent.is_finite() {
+            Ok(Self { origin, extent })
+        } else {
+            Err(OctreeError::InvalidInput(format!(
+                "world extent {} is not a positive finite number",
+                extent
+            )))
+        }
+    }
+
+    /// Panics if `voxel_size` is not a positive finite number, see
+    /// `try_from_voxel_size`.
+    pub fn from_voxel_size(origin: (f64, f64, f64), voxel_size: f64, max_depth: u32) -> Self {
+        Self::try_from_voxel_size(origin, voxel_size, max_depth)
+            .expect("the voxel size must be positive")
+    }
+
+    /// Transform giving each voxel of a tree of the given `max_depth` an edge
+    /// of `voxel_size` units.
+    pub fn try_from_voxel_size(
+        origin: (f64, f64, f64),
+        voxel_size: f64,
+        max_depth: u32,
+    ) -> Result<Self, OctreeError> {
+        Self::try_new(origin, voxel_size * (1_u64 << max_depth) as f64)
+    }
+
+    pub fn origin(&self) -> (f64, f64, f64) {
+        self.origin
+    }
+
+    /// Edge length of the whole tree in world units.
+    pub fn extent(&self) -> f64 {
+        self.extent
+    }
+
+    /// Edge length of a voxel of a tree of the given `max_depth`.
+    pub fn voxel_size(&self, max_depth: u32) -> f64 {
+        self.extent / (1_u64 << max_depth) as f64
+    }
+
+    /// World space covered by the tree.
+    pub fn bounds(&self) -> AABB {
+        self.aabb_to_world(&AABB::new(0.0, 0.0, 0.0, 1.0, 1.0, 1.0))
+    }
+
+    /// Position of a world point in the unit cube of the tree.
+    pub fn to_unit(&self, point: (f64, f64, f64)) -> (f64, f64, f64) {
+        (
+            (point.0 - self.origin.0) / self.extent,
+            (point.1 - self.origin.1) / self.extent,
+            (point.2 - self.origin.2) / self.extent,
+        )
+    }
+
+    /// World position of a point of the unit cube of the tree.
+    pub fn to_world(&self, point: (f64, f64, f64)) -> (f64, f64, f64) {
+        (
+            point.0 * self.extent + self.origin.0,
+            point.1 * self.extent + self.origin.1,
+            point.2 * self.extent + self.origin.2,
+        )
+    }
+
+    pub fn aabb_to_unit(&self, aabb: &AABB) -> AABB {
+        let (x1, y1, z1) = self.to_unit(aabb.min());
+        let (x2, y2, z2) = self.to_unit(aabb.max());
+        AABB::new(x1, y1, z1, x2, y2, z2)
+    }
+
+    pub fn aabb_to_world(&self, aabb: &AABB) -> AABB {
+        let (x1, y1, z1) = self.to_world(aabb.min());
+        let (x2, y2, z2) = self.to_world(aabb.max());
+        AABB::new(x1, y1, z1, x2, y2, z2)
+    }
+}
+
+impl<T, D, S> Octree<T, D, S>
+where
+    T: LocCode,
+    D: Copy + PartialEq + Send + Sync,
+    S: OctreeStorage<T, D>,
+{
+    /// Transform placing the tree in world space, the unit cube when unset.
+    pub fn world_transform(&self) -> WorldTransform {
+        self.world_transform.unwrap_or_default()
+    }
+
+    pub fn set_world_transform(&mut self, world_transform: Option<WorldTransform>) {
+        self.world_transform = world_transform;
+    }
+
+    pub fn with_world_transform(mut self, world_transform: WorldTransform) -> Self {
+        self.world_transform = Some(world_transform);
+        self
+    }
+
+    /// Merge a box given in world space, see `merge`.
+    pub fn merge_world(&mut self, aabb: AABB, data: D) -> Result<(), OctreeError> {
+        let aabb = self.world_transform().aabb_to_unit(&aabb);
+        self.merge(aabb, data)
+    }
+
+    /// Voxel of the `2^max_depth` grid containing a world point, `None` if
+    /// it is outside of the tree.
+    pub fn world_to_voxel(&self, point: (f64, f64, f64)) -> Option<(u64, u64, u64)> {
+        let side = (1_u64 << self.max_depth()) as f64;
+        let (x, y, z) = self.world_transform().to_unit(point);
+        let axis = |unit: f64| {
+            if (0.0..1.0).contains(&unit) {
+                Some(((unit * side) as u64).min(side as u64 - 1))
+            } else {
+                None
+            }
+        };
+        Some((axis(x)?, axis(y)?, axis(z)?))
+    }
+
+    /// Leaf containing a world point, see `leaf_at`.
+    pub fn leaf_at_world(&self, point: (f64, f64, f64)) -> Option<(Morton<T>, &OctreeNode<D>)> {
+        self.leaf_at(self.world_to_voxel(point)?)
+    }
+
+    /// World space covered by a node.
+    pub fn world_aabb(&self, loc_code: Morton<T>) -> AABB {
+        self.world_transform()
+            .aabb_to_world(&AABB::from_loc_code(loc_code.get()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn world_space() {
+        let transform = WorldTransform::from_voxel_size((-4.0, 0.0, 10.0), 0.5, 3);
+        assert_eq!(transform.extent(), 4.0);
+        assert_eq!(transform.to_unit((-2.0, 1.0, 14.0)), (0.5, 0.25, 1.0));
+        assert_eq!(transform.to_world((0.5, 0.25, 1.0)), (-2.0, 1.0, 14.0));
+
+        let mut tree: Octree<u32, u8> = Octree::new(3).with_world_transform(transform);
+        tree.merge_world(AABB::new(-4.0, 0.0, 10.0, -2.0, 2.0, 12.0), 1)
+            .unwrap();
+        assert_eq!(tree.lookup(Morton::new(0b1_000).unwrap()).unwrap().data, 1);
+        assert_eq!(tree.world_to_voxel((-3.9, 1.9, 10.1)), Some((0, 3, 0)));
+        assert_eq!(tree.world_to_voxel((0.0, 0.0, 10.0)), None);
+        let (code, node) = tree.leaf_at_world((-3.0, 1.0, 11.0)).unwrap();
+        assert_eq!(node.data, 1);
+        let bounds = tree.world_aabb(code);
+        assert_eq!(bounds.min(), (-4.0, 0.0, 10.0));
+        assert_eq!(bounds.max(), (-2.0, 2.0, 12.0));
+        assert!(tree.leaf_at_world((-1.0, 1.0, 11.0)).is_none());
+    }
+
+    #[test]
+    fn invalid_extent() {
+        for extent in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                WorldTransform::try_new((0.0, 0.0, 0.0), *extent),
+                Err(OctreeError::InvalidInput(_))
+            ));
+        }
+        assert!(WorldTransform::try_from_voxel_size((0.0, 0.0, 0.0), 0.0, 3).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "dot_tree")]
+    fn deserialize_invalid_extent() {
+        let transform = WorldTransform::new((1.0, 2.0, 3.0), 4.0);
+        let bytes = bincode::serialize(&transform).unwrap();
+        assert_eq!(
+            bincode::deserialize::<WorldTransform>(&bytes).unwrap(),
+            transform
+        );
+        let bytes = bincode::serialize(&((1.0, 2.0, 3.0), -4.0)).unwrap();
+        assert!(bincode::deserialize::<WorldTransform>(&bytes).is_err());
+    }
+}
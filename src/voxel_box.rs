@@ -0,0 +1,218 @@
+//
+// voxel_box.rs in generic_octree
+// Description:
+// Integer boxes on the voxel grid of an Octree.
+//
+
+use crate::loc_code::octant_axes;
+use crate::{LocCode, Orientation, PlaneAxis};
+
+/// Half-open box of voxels at the `max_depth` of a tree, the root cube
+/// spanning `0..2^max_depth` on every axis.
+///
+/// This is the exact counterpart of `AABB`: coordinates outside of the root
+/// cube are allowed and ignored when merging.
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelBox {
+    min: [i64; 3],
+    max: [i64; 3],
+    pub orientation: Orientation,
+}
+
+impl VoxelBox {
+    pub fn new(x1: i64, y1: i64, z1: i64, x2: i64, y2: i64, z2: i64) -> Self {
+        Self {
+            min: [x1.min(x2), y1.min(y2), z1.min(z2)],
+            max: [x1.max(x2), y1.max(y2), z1.max(z2)],
+            orientation: Orientation::default(),
+        }
+    }
+
+    pub(crate) fn from_corners(min: [i64; 3], max: [i64; 3]) -> Self {
+        Self::new(min[0], min[1], min[2], max[0], max[1], max[2])
+    }
+
+    /// Box of a single voxel.
+    pub fn voxel(x: i64, y: i64, z: i64) -> Self {
+        Self::new(x, y, z, x + 1, y + 1, z + 1)
+    }
+
+    /// Voxels covered by a node of a tree of the given `max_depth`, `None`
+    /// if the node is deeper than `max_depth`.
+    pub fn from_loc_code<L: LocCode>(loc_code: L, max_depth: u32) -> Option<Self> {
+        let size = 1_i64 << max_depth.checked_sub(loc_code.get_depth())?;
+        let (x, y, z) = loc_code.to_coords();
+        let (x, y, z) = (x as i64 * size, y as i64 * size, z as i64 * size);
+        Some(Self::new(x, y, z, x + size, y + size, z + size))
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Lowest voxel of the box.
+    pub fn min(&self) -> (i64, i64, i64) {
+        (self.min[0], self.min[1], self.min[2])
+    }
+
+    /// Voxel past the highest one of the box.
+    pub fn max(&self) -> (i64, i64, i64) {
+        (self.max[0], self.max[1], self.max[2])
+    }
+
+    /// Number of voxels along each axis.
+    pub fn extents(&self) -> (i64, i64, i64) {
+        (
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        )
+    }
+
+    /// Number of voxels in the box.
+    pub fn volume(&self) -> u128 {
+        let (x, y, z) = self.extents();
+        x as u128 * y as u128 * z as u128
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] >= self.max[axis])
+    }
+
+    /// Whether `other` is entirely inside the box.
+    pub fn contains(&self, other: &Self) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis])
+    }
+
+    /// Whether the boxes share some voxels.
+    pub fn intersects(&self, other: &Self) -> bool {
+        (0..3).all(|axis| self.min[axis] < other.max[axis] && other.min[axis] < self.max[axis])
+    }
+
+    /// Restrict the box to the root cube of a tree of the given `max_depth`,
+    /// `None` if nothing is left.
+    pub fn clip(&self, max_depth: u32) -> Option<Self> {
+        let side = 1_i64 << max_depth;
+        let mut clipped = *self;
+        for axis in 0..3 {
+            clipped.min[axis] = clipped.min[axis].max(0);
+            clipped.max[axis] = clipped.max[axis].min(side);
+        }
+        if clipped.is_empty() {
+            None
+        } else {
+            Some(clipped)
+        }
+    }
+
+    /// Move the box by `offset` and grow it by `grow` on both sides.
+    pub(crate) fn shift_grow(&self, offset: [i64; 3], grow: [i64; 3]) -> Self {
+        let mut shifted = *self;
+        for axis in 0..3 {
+            shifted.min[axis] += offset[axis] - grow[axis];
+            shifted.max[axis] += offset[axis] + grow[axis];
+        }
+        shifted
+    }
+
+    /// Split the box in two along `axis` at `plane`, tagging each part with
+    /// the side of the plane it is on.
+    fn slice(self, axis: PlaneAxis, plane: i64) -> Vec<Self> {
+        let index = axis as usize;
+        let orientation = self.orientation;
+        let below = self.with_orientation(orientation.with_axis(axis, false));
+        let above = self.with_orientation(orientation.with_axis(axis, true));
+        if self.min[index] < plane && self.max[index] > plane {
+            let (mut below, mut above) = (below, above);
+            below.max[index] = plane;
+            above.min[index] = plane;
+            vec![below, above]
+        } else if self.max[index] <= plane {
+            vec![below]
+        } else {
+            vec![above]
+        }
+    }
+
+    /// Split the box into the octants around `center`, as `AABB::explode`.
+    pub fn explode(self, center: (i64, i64, i64)) -> Vec<Self> {
+        self.slice(PlaneAxis::X, center.0)
+            .into_iter()
+            .flat_map(|part| part.slice(PlaneAxis::Y, center.1))
+            .flat_map(|part| part.slice(PlaneAxis::Z, center.2))
+            .collect()
+    }
+
+    /// Whether an exploded part fills its whole node at `depth`.
+    pub fn fit_in(&self, depth: u32, max_depth: u32) -> bool {
+        let size = 1_i64 << (max_depth - depth);
+        depth == max_depth || (0..3).all(|axis| self.max[axis] - self.min[axis] == size)
+    }
+
+    /// Center of the child `loc_code` of the node centered on `center`.
+    pub(crate) fn child_center<L: LocCode>(
+        loc_code: L,
+        center: (i64, i64, i64),
+        max_depth: u32,
+    ) -> (i64, i64, i64) {
+        let offset = 1_i64 << (max_depth - loc_code.get_depth()) >> 1;
+        let (x, y, z) = octant_axes(loc_code);
+        let shift = |axis: u64| if axis == 1 { offset } else { -offset };
+        (
+            center.0 + shift(x),
+            center.1 + shift(y),
+            center.2 + shift(z),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Octree, AABB};
+
+    #[test]
+    fn explode() {
+        let parts = VoxelBox::new(1, 0, 3, 6, 4, 4).explode((4, 4, 4));
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].extents(), (3, 4, 1));
+        assert!(parts[0].fit_in(3, 3));
+        assert!(!parts[1].fit_in(1, 3));
+        assert_eq!(parts[1].orientation, Orientation::RBD);
+        assert!(VoxelBox::new(4, 0, 0, 8, 4, 4).explode((4, 4, 4))[0].fit_in(1, 3));
+    }
+
+    #[test]
+    fn merge_box_matches_merge() {
+        let mut from_aabb: Octree<u32, u8> = Octree::new(3);
+        from_aabb
+            .merge(AABB::new(0.25, 0.0, 0.125, 0.75, 0.5, 1.0), 1)
+            .unwrap();
+        let mut from_box: Octree<u32, u8> = Octree::new(3);
+        from_box
+            .merge_box(VoxelBox::new(2, 0, 1, 6, 4, 8), 1)
+            .unwrap();
+        let mut expected: Vec<u32> = from_aabb.iter().map(|(code, _)| code).collect();
+        let mut codes: Vec<u32> = from_box.iter().map(|(code, _)| code).collect();
+        expected.sort();
+        codes.sort();
+        assert_eq!(codes, expected);
+    }
+
+    #[test]
+    fn from_loc_code() {
+        let node = VoxelBox::from_loc_code(u32::from_coords(1, 0, 1, 1), 3).unwrap();
+        assert_eq!(node.min(), (4, 0, 4));
+        assert_eq!(node.max(), (8, 4, 8));
+        assert!(VoxelBox::from_loc_code(u32::from_coords(1, 0, 1, 2), 1).is_none());
+    }
+
+    #[test]
+    fn clip() {
+        let clipped = VoxelBox::new(-2, 3, 6, 2, 5, 12).clip(3).unwrap();
+        assert_eq!(clipped.min(), (0, 3, 6));
+        assert_eq!(clipped.max(), (2, 5, 8));
+        assert!(VoxelBox::new(8, 0, 0, 9, 1, 1).clip(3).is_none());
+    }
+}
@@ -0,0 +1,285 @@
+//
+// world.rs in generic_octree
+// Description:
+// Unbounded world of octree chunks paged in and out of a directory.
+//
+
+use hashbrown::{HashMap, HashSet};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{LocCode, Octree, OctreeError, WorldTransform, AABB};
+
+/// Integer coordinates of a chunk, chunk `(0, 0, 0)` spanning
+/// `[0, chunk_size)` on every axis.
+pub type ChunkKey = (i64, i64, i64);
+
+/// Grid of octrees, each covering a `chunk_size` wide cube of world space
+/// and stored as a `.tree` file in `directory`.
+///
+/// At most `budget` chunks are kept in memory, the least recently used ones
+/// being saved and dropped when more are needed. Edited chunks are written
+/// when unloaded, on `flush` and, on a best-effort basis, when the world is
+/// dropped.
+#[derive(Debug)]
+pub struct OctreeWorld<L, D>
+where
+    L: LocCode + Serialize + DeserializeOwned,
+    D: Copy + PartialEq + Send + Sync + Serialize + DeserializeOwned,
+{
+    directory: PathBuf,
+    chunk_size: f64,
+    max_depth: u32,
+    budget: usize,
+    chunks: HashMap<ChunkKey, Octree<L, D>>,
+    dirty: HashSet<ChunkKey>,
+    last_used: HashMap<ChunkKey, u64>,
+    clock: u64,
+}
+
+impl<L, D> OctreeWorld<L, D>
+where
+    L: LocCode + Serialize + DeserializeOwned,
+    D: Copy + PartialEq + Send + Sync + Serialize + DeserializeOwned,
+{
+    /// Open or create a world in `directory`, keeping at least one chunk
+    /// loaded whatever `budget` is.
+    pub fn new<P: AsRef<Path>>(
+        directory: P,
+        chunk_size: f64,
+        max_depth: u32,
+        budget: usize,
+    ) -> Result<Self, OctreeError> {
+        Octree::<L, D>::try_new(max_depth)?;
+        if chunk_size <= 0.0 {
+            return Err(OctreeError::InvalidInput(
+                "the chunk size must be positive".to_string(),
+            ));
+        }
+        std::fs::create_dir_all(directory.as_ref())?;
+        Ok(Self {
+            directory: directory.as_ref().to_path_buf(),
+            chunk_size,
+            max_depth,
+            budget: budget.max(1),
+            chunks: HashMap::new(),
+            dirty: HashSet::new(),
+            last_used: HashMap::new(),
+            clock: 0,
+        })
+    }
+
+    pub fn chunk_size(&self) -> f64 {
+        self.chunk_size
+    }
+
+    /// Chunk containing a world point.
+    pub fn chunk_key(&self, point: (f64, f64, f64)) -> ChunkKey {
+        (
+            (point.0 / self.chunk_size).floor() as i64,
+            (point.1 / self.chunk_size).floor() as i64,
+            (point.2 / self.chunk_size).floor() as i64,
+        )
+    }
+
+    /// World space covered by a chunk.
+    pub fn chunk_bounds(&self, key: ChunkKey) -> AABB {
+        self.chunk_transform(key).bounds()
+    }
+
+    fn chunk_transform(&self, key: ChunkKey) -> WorldTransform {
+        let origin = (
+            key.0 as f64 * self.chunk_size,
+            key.1 as f64 * self.chunk_size,
+            key.2 as f64 * self.chunk_size,
+        );
+        WorldTransform::new(origin, self.chunk_size)
+    }
+
+    fn chunk_path(&self, key: ChunkKey) -> PathBuf {
+        self.directory
+            .join(format!("chunk_{}_{}_{}.tree", key.0, key.1, key.2))
+    }
+
+    /// Chunks currently in memory.
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = ChunkKey> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    /// Chunk at `key`, loaded from disk if needed, `None` if it was never
+    /// saved and `create` is not set.
+    fn load(
+        &mut self,
+        key: ChunkKey,
+        create: bool,
+    ) -> Result<Option<&mut Octree<L, D>>, OctreeError> {
+        if !self.chunks.contains_key(&key) {
+            let path = self.chunk_path(key);
+            let exists = path.exists();
+            if !exists && !create {
+                return Ok(None);
+            }
+            while self.chunks.len() >= self.budget {
+                self.evict()?;
+            }
+            let tree = if exists {
+                let mut tree = Octree::load_from_file(&path)?;
+                if tree.max_depth() != self.max_depth {
+                    return Err(OctreeError::Decode(
+                        "chunk max_depth does not match the world".to_string(),
+                    ));
+                }
+                tree.set_world_transform(Some(self.chunk_transform(key)));
+                tree
+            } else {
+                Octree::try_new(self.max_depth)?.with_world_transform(self.chunk_transform(key))
+            };
+            self.chunks.insert(key, tree);
+        }
+        self.clock += 1;
+        self.last_used.insert(key, self.clock);
+        Ok(self.chunks.get_mut(&key))
+    }
+
+    /// Chunk at `key`, loaded from disk or created empty if needed.
+    pub fn chunk(&mut self, key: ChunkKey) -> Result<&Octree<L, D>, OctreeError> {
+        self.load(key, true)?;
+        Ok(&self.chunks[&key])
+    }
+
+    /// Chunk at `key` to edit, loaded from disk or created empty if needed,
+    /// and saved again when it is unloaded or flushed.
+    pub fn chunk_mut(&mut self, key: ChunkKey) -> Result<&mut Octree<L, D>, OctreeError> {
+        self.load(key, true)?;
+        self.dirty.insert(key);
+        Ok(self.chunks.get_mut(&key).expect("chunk was just loaded"))
+    }
+
+    /// Save and drop the least recently used chunk.
+    fn evict(&mut self) -> Result<(), OctreeError> {
+        let oldest = self
+            .chunks
+            .keys()
+            .min_by_key(|key| self.last_used.get(*key).copied().unwrap_or(0))
+            .copied();
+        match oldest {
+            Some(key) => self.unload(key),
+            None => Ok(()),
+        }
+    }
+
+    /// Save a chunk if it was edited, then drop it from memory.
+    pub fn unload(&mut self, key: ChunkKey) -> Result<(), OctreeError> {
+        if self.dirty.contains(&key) {
+            if let Some(tree) = self.chunks.get(&key) {
+                tree.save_to_file(self.chunk_path(key))?;
+            }
+            self.dirty.remove(&key);
+        }
+        self.chunks.remove(&key);
+        self.last_used.remove(&key);
+        Ok(())
+    }
+
+    /// Save every edited chunk, keeping them loaded.
+    pub fn flush(&mut self) -> Result<(), OctreeError> {
+        let dirty: Vec<ChunkKey> = self.dirty.iter().copied().collect();
+        for key in dirty {
+            if let Some(tree) = self.chunks.get(&key) {
+                tree.save_to_file(self.chunk_path(key))?;
+            }
+            self.dirty.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Merge a box given in world space, split between every chunk it
+    /// overlaps.
+    pub fn merge(&mut self, aabb: AABB, data: D) -> Result<(), OctreeError> {
+        let (min, max) = (self.chunk_key(aabb.min()), self.chunk_key(aabb.max()));
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let key = (x, y, z);
+                    if let Some(part) = aabb.intersection(&self.chunk_bounds(key)) {
+                        self.chunk_mut(key)?.merge_world(part, data)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Data of the leaf containing a world point, without creating a chunk
+    /// where none was ever saved.
+    pub fn get(&mut self, point: (f64, f64, f64)) -> Result<Option<D>, OctreeError> {
+        let key = self.chunk_key(point);
+        Ok(self
+            .load(key, false)?
+            .and_then(|chunk| chunk.leaf_at_world(point))
+            .map(|(_, node)| node.data))
+    }
+}
+
+/// Saves the edited chunks, ignoring errors: call `flush` to handle them.
+impl<L, D> Drop for OctreeWorld<L, D>
+where
+    L: LocCode + Serialize + DeserializeOwned,
+    D: Copy + PartialEq + Send + Sync + Serialize + DeserializeOwned,
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paging() {
+        let directory = std::env::temp_dir().join(format!("world-{}", std::process::id()));
+        let mut world: OctreeWorld<u32, u8> = OctreeWorld::new(&directory, 8.0, 3, 1).unwrap();
+        world
+            .merge(AABB::new(-2.0, 0.0, 0.0, 3.0, 1.0, 1.0), 5)
+            .unwrap();
+        assert_eq!(world.loaded_chunks().count(), 1);
+        assert!(directory.join("chunk_-1_0_0.tree").exists());
+        assert_eq!(world.get((-1.5, 0.5, 0.5)).unwrap(), Some(5));
+        assert_eq!(world.get((2.5, 0.5, 0.5)).unwrap(), Some(5));
+        assert_eq!(world.get((3.5, 0.5, 0.5)).unwrap(), None);
+        assert_eq!(world.get((-2.5, 0.5, 0.5)).unwrap(), None);
+        world.flush().unwrap();
+
+        let mut reopened: OctreeWorld<u32, u8> = OctreeWorld::new(&directory, 8.0, 3, 4).unwrap();
+        assert_eq!(reopened.get((0.5, 0.5, 0.5)).unwrap(), Some(5));
+        assert_eq!(reopened.get((-0.5, 0.9, 0.1)).unwrap(), Some(5));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn edits_are_saved() {
+        let directory = std::env::temp_dir().join(format!("world-edits-{}", std::process::id()));
+        let mut world: OctreeWorld<u32, u8> = OctreeWorld::new(&directory, 4.0, 2, 1).unwrap();
+        assert_eq!(world.get((9.0, 1.0, 1.0)).unwrap(), None);
+        assert_eq!(world.loaded_chunks().count(), 0);
+        world
+            .chunk_mut((0, 0, 0))
+            .unwrap()
+            .merge(AABB::new(0.0, 0.0, 0.0, 0.5, 0.5, 0.5), 3)
+            .unwrap();
+        // Loading another chunk evicts the edited one.
+        world.chunk((1, 0, 0)).unwrap();
+        assert_eq!(world.get((1.0, 1.0, 1.0)).unwrap(), Some(3));
+        world
+            .chunk_mut((2, 0, 0))
+            .unwrap()
+            .merge(AABB::new(0.0, 0.0, 0.0, 1.0, 1.0, 1.0), 4)
+            .unwrap();
+        drop(world);
+
+        let mut reopened: OctreeWorld<u32, u8> = OctreeWorld::new(&directory, 4.0, 2, 4).unwrap();
+        assert_eq!(reopened.get((9.0, 1.0, 1.0)).unwrap(), Some(4));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}